@@ -1,4 +1,5 @@
 use crate::bitboard::Bitboard;
+use crate::global::GlobalData;
 use crate::types::{Color, Kind, Piece, Square};
 
 // Pieces only
@@ -66,6 +67,21 @@ impl Board {
         (self.kind_bb(Kind::Rook) | self.kind_bb(Kind::Queen)) & self.color_bb(color)
     }
 
+    pub fn attackers(&self, square: Square, color: Color) -> Bitboard {
+        let global = GlobalData::get();
+        let attack = global.attack();
+        let magic = global.magic();
+        let occupied = self.occupied_bb();
+
+        let bb = attack.pawn(square, !color) & self.kind_bb(Kind::Pawn)
+            | attack.knight(square) & self.kind_bb(Kind::Knight)
+            | attack.king(square) & self.kind_bb(Kind::King)
+            | magic.bishop(square, occupied) & self.bishop_queen_bb(color)
+            | magic.rook(square, occupied) & self.rook_queen_bb(color);
+
+        bb & self.color_bb(color)
+    }
+
     pub fn is_king_and_pawn(&self, color: Color) -> bool {
         let bb = self.color_bb(color);
 
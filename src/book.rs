@@ -1,6 +1,7 @@
 use crate::{position::Position, r#move::Move};
 use std::collections::HashMap;
 use rand::distr::{weighted::WeightedIndex, Distribution};
+use rand::Rng;
 
 pub struct Book {
     openings: HashMap<u64, (Vec<Move>, Vec<usize>)>,
@@ -51,11 +52,10 @@ impl Book {
         Some(entry.0[0])
     }
 
-    pub fn next(&self, position: &Position) -> Option<Move> {
+    pub fn next(&self, position: &Position, rng: &mut impl Rng) -> Option<Move> {
         let entry = self.openings.get(&position.hash())?;
         let dist = WeightedIndex::new(&entry.1).unwrap();
-        let mut rng = rand::rng();
 
-        Some(entry.0[dist.sample(&mut rng)])
+        Some(entry.0[dist.sample(rng)])
     }
 }
@@ -1,17 +1,34 @@
-use std::time::Instant;
-use std::{env, path::Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{env, fs, mem, path::Path};
+
+use rand::{rngs::StdRng, SeedableRng};
 
 use crate::options::Options;
 use crate::{
-    book::Book, gen::*, position::Position, r#move::Move, search, searchlimits::SearchLimits,
+    bench,
+    book::Book,
+    epd::Epd,
+    gen,
+    perft::{self, Perft},
+    position::Position,
+    search,
+    searchlimits::SearchLimits,
     tt::TranspositionTable,
+    uci::Command,
 };
 
 pub struct Engine {
     position: Position,
     tt: TranspositionTable,
     book: Book,
+    rng: Mutex<StdRng>,
     age: u32,
+    stop: Arc<AtomicBool>,
+    ponder: Arc<AtomicBool>,
+    debug: Arc<AtomicBool>,
     pub options: Options,
 }
 
@@ -20,12 +37,18 @@ impl Engine {
     const AUTHOR: &'static str = "csteenvo mjoosten";
 
     pub fn new() -> Self {
+        let options = Options::new();
+
         Self {
             position: Position::new(),
-            tt: TranspositionTable::new(),
+            tt: TranspositionTable::new(options.hash).expect("cannot allocate the hash table"),
             book: Book::new(),
+            rng: Mutex::new(Self::seeded_rng(options.seed)),
             age: 0,
-            options: Options::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            ponder: Arc::new(AtomicBool::new(false)),
+            debug: Arc::new(AtomicBool::new(false)),
+            options,
         }
     }
 
@@ -49,12 +72,36 @@ impl Engine {
         &self.book
     }
 
+    // All random choices go through this, so runs repeat for a fixed Seed
+    pub fn rng(&self) -> MutexGuard<'_, StdRng> {
+        self.rng.lock().unwrap()
+    }
+
+    // Runs repeat with the default seed, 0 asks for a different seed every run
+    fn seeded_rng(seed: u64) -> StdRng {
+        match seed {
+            0 => StdRng::from_os_rng(),
+            _ => StdRng::seed_from_u64(seed),
+        }
+    }
+
     pub fn age(&self) -> u32 {
         self.age
     }
 
-    pub fn run(&mut self) {
-        let mut quit = false;
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.ponder.load(Ordering::Relaxed)
+    }
+
+    pub fn is_debug(&self) -> bool {
+        self.debug.load(Ordering::Relaxed)
+    }
+
+    pub fn run(self) {
         let mut name = Self::NAME.to_string();
 
         if let Some(arg) = env::args().nth(0) {
@@ -63,7 +110,13 @@ impl Engine {
             name = format!("{}", path.file_name().unwrap().to_str().unwrap());
         }
 
-        while !quit {
+        let stop = self.stop.clone();
+        let ponder = self.ponder.clone();
+        let debug = self.debug.clone();
+        let engine = Arc::new(Mutex::new(self));
+        let mut search = None;
+
+        loop {
             let mut buffer = String::new();
 
             if let Ok(bytes) = std::io::stdin().read_line(&mut buffer) {
@@ -72,153 +125,316 @@ impl Engine {
                 }
             }
 
-            let words: Vec<_> = buffer.split_whitespace().collect();
+            let command = match Command::parse(&buffer) {
+                Ok(Some(command)) => command,
+                Ok(None) => continue,
+                Err(err) => {
+                    println!("info string {err}");
+                    continue;
+                }
+            };
 
-            if let Some(command) = words.first() {
-                match *command {
-                    "uci" => {
-                        println!("id name {name}");
-                        println!("id author {}", Self::AUTHOR);
-                        // println!("option name OwnBook value check");
-                        println!("uciok");
-                    }
-                    "debug" => {
-                        if let Some(&arg) = words.iter().nth(1) {
-                            match arg {
-                                "on" => self.options.debug = true,
-                                "off" => self.options.debug = false,
-                                _ => (),
-                            }
-                        }
+            match command {
+                // Handled while searching
+                Command::Uci => Self::uci(&name),
+                Command::Debug(value) => debug.store(value, Ordering::Relaxed),
+                Command::IsReady => println!("readyok"),
+                Command::Stop => Self::stop_search(&stop, &mut search),
+                Command::PonderHit => ponder.store(false, Ordering::Relaxed),
+                Command::Quit => break,
+                Command::Go(mut limits) => {
+                    Self::stop_search(&stop, &mut search);
+
+                    if !engine.lock().unwrap().check_searchmoves(&mut limits) {
+                        println!("bestmove 0000");
+                        continue;
                     }
-                    "isready" => println!("readyok"),
-                    "setoption" => self.setoption(&words[1..]),
-                    "ucinewgame" => self.position = Position::new(),
-                    "position" => self.uci_position(&words[1..]),
-                    "go" => self.go(&words[1..]),
-                    "quit" => quit = true,
-                    "perft" => {
-                        let start = Instant::now();
-                        self.uci_perft(&words[1..]);
-                        eprintln!("took {:?}", start.elapsed());
-                    }
-                    _ => (),
+
+                    stop.store(false, Ordering::Relaxed);
+                    ponder.store(limits.ponder(), Ordering::Relaxed);
+
+                    let engine = engine.clone();
+
+                    search = Some(thread::spawn(move || engine.lock().unwrap().go(&limits)));
+                }
+                // These change what the search is working on
+                Command::Position(_) | Command::UciNewGame | Command::SetOption { .. } => {
+                    Self::stop_search(&stop, &mut search);
+
+                    engine.lock().unwrap().command(command);
+                }
+                _ if search.as_ref().is_some_and(|search| !search.is_finished()) => {
+                    println!(
+                        "info string {} is not available while searching",
+                        buffer.trim()
+                    );
                 }
+                command => engine.lock().unwrap().command(command),
             }
         }
-    }
 
-    pub fn uci_position(&mut self, words: &[&str]) {
-        if let Some(pos) = words.first() {
-            let fen: [&str; 6] = match *pos {
-                "fen" => words
-                    .iter()
-                    .skip(1)
-                    .copied()
-                    .take_while(|&s| s != "moves")
-                    .collect::<Vec<&str>>()
-                    .try_into()
-                    .unwrap(),
-                "startpos" => Position::STARTPOS
-                    .split_whitespace()
-                    .collect::<Vec<&str>>()
-                    .try_into()
-                    .unwrap(),
-                _ => panic!(),
-            };
-
-            self.position = Position::parse(&fen);
+        Self::stop_search(&stop, &mut search);
+    }
 
-            for m in words.iter().skip_while(|&s| *s != "moves").skip(1) {
-                self.position.make(Move::from_str(m));
-            }
+    fn stop_search(stop: &AtomicBool, search: &mut Option<JoinHandle<()>>) {
+        if let Some(handle) = search.take() {
+            stop.store(true, Ordering::Relaxed);
+            handle.join().unwrap();
         }
     }
 
-    pub fn go(&mut self, words: &[&str]) {
-        if let Some(command) = words.first() {
-            if *command == "perft" {
-                self.uci_perft(&words[1..]);
+    // Reports and drops searchmoves that are illegal here, false if none are left
+    fn check_searchmoves(&self, limits: &mut SearchLimits) -> bool {
+        if limits.searchmoves().is_empty() {
+            return true;
+        }
 
-                return;
-            }
+        let mut legal = Vec::new();
+
+        gen::generate_dyn::<true>(&mut legal, &self.position);
+
+        let (searchmoves, illegal): (Vec<_>, Vec<_>) = limits
+            .searchmoves()
+            .iter()
+            .partition(|r#move| legal.contains(r#move));
+
+        for r#move in illegal {
+            println!("info string illegal searchmove {move}");
         }
 
-        let limits = SearchLimits::parse(words);
+        let any = !searchmoves.is_empty();
 
-        println!(
-            "bestmove {}",
-            search::search(self, limits.get_end_time(self.position.turn()), &limits)
-        );
+        limits.set_searchmoves(searchmoves);
 
-        self.age += 1;
+        any
     }
 
-    pub fn uci_perft(&mut self, words: &[&str]) {
-        let depth = words.first().map(|s| s.parse().unwrap()).unwrap_or(1);
+    fn uci(name: &str) {
+        println!("id name {name}");
+        println!("id author {}", Self::AUTHOR);
 
-        println!("Nodes searched: {}", self.perft(depth, true));
+        for option in &Options::DECLARED {
+            println!("{option}");
+        }
+
+        println!("uciok");
     }
 
-    pub fn perft(&mut self, depth: usize, root: bool) -> usize {
-        if depth == 0 {
-            return 1;
-        } else if depth == 1 && !root {
-            let mut total = 0;
+    pub fn command(&mut self, command: Command) {
+        match command {
+            Command::SetOption { name, value } => self.setoption(&name, &value),
+            Command::UciNewGame => self.position = Position::new(),
+            Command::Position(position) => self.position = position,
+            Command::Perft(depth) => {
+                let start = Instant::now();
+                self.uci_perft(depth);
+                eprintln!("took {:?}", start.elapsed());
+            }
+            Command::PerftSuite { path, depth } => {
+                let start = Instant::now();
+                self.uci_perftsuite(&path, depth);
+                eprintln!("took {:?}", start.elapsed());
+            }
+            Command::Bench {
+                depth,
+                hash,
+                threads,
+            } => {
+                self.bench(depth, hash, threads);
+            }
+            Command::Eval => println!("{}", self.position.evaluate_trace()),
+            _ => (),
+        }
+    }
 
-            generate_dyn::<true>(&mut total, &self.position);
+    pub fn go(&mut self, limits: &SearchLimits) {
+        let pv = search::search(self, limits).pv;
 
-            return total;
+        // Never send bestmove before the GUI allows it
+        while !self.is_stopped() && (limits.infinite() || self.is_pondering()) {
+            thread::sleep(Duration::from_millis(1));
         }
 
-        let mut moves = MoveVec::new();
+        match pv[..] {
+            [] => println!("bestmove 0000"),
+            [best] => println!("bestmove {best}"),
+            [best, ponder, ..] => println!("bestmove {best} ponder {ponder}"),
+        }
 
-        generate_dyn::<true>(&mut moves, &self.position);
+        self.age += 1;
+    }
 
+    pub fn uci_perft(&mut self, depth: usize) {
         let mut total = 0;
 
-        for m in moves.moves() {
-            let undo = self.position.make(*m);
-            let count = self.perft(depth - 1, false);
+        let perft = Perft::new(self.options.threads, self.options.perft_hash);
 
-            if root {
-                println!("{m}: {count}");
-            }
+        for (m, count) in perft.divide(&self.position, depth) {
+            println!("{m}: {count}");
 
             total += count;
-
-            self.position.unmake(undo);
         }
 
-        total
+        println!("Nodes searched: {total}");
     }
 
-    pub fn setoption(&mut self, words: &[&str]) {
-        let mut it = words.into_iter().map(|&s| s);
+    pub fn perft(&mut self, depth: usize) -> usize {
+        perft::perft(&mut self.position, depth)
+    }
+
+    pub fn uci_perftsuite(&mut self, path: &str, max_depth: usize) {
+        let file = match fs::read_to_string(path) {
+            Ok(file) => file,
+            Err(err) => return println!("info string cannot read {path}: {err}"),
+        };
+
+        let perft = Perft::new(self.options.threads, self.options.perft_hash);
+        let mut passed = 0;
+        let mut total = 0;
+
+        for (index, line) in file.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let epd = match Epd::parse(line) {
+                Ok(epd) => epd,
+                Err(err) => {
+                    println!("info string line {}: {err}", index + 1);
+                    continue;
+                }
+            };
+
+            let fen = epd.position.fen();
+            let results = perft.run_epd(&epd, max_depth);
+
+            total += 1;
+
+            match results.last() {
+                Some(result) if !result.passed() => {
+                    println!(
+                        "{fen}: fail at depth {}, expected {} got {}",
+                        result.depth, result.expected, result.nodes
+                    );
 
-        if let Some(name) = Self::optionarg("name", &mut it) {
-            if let Some(value) = Self::optionarg("value", &mut it) {
-                match name {
-                    "OwnBook" => {
-                        match value {
-                            "true" => self.options.ownbook = true,
-                            "false" => self.options.ownbook = false,
-                            _ => (),
-                        }
+                    // Print the divide at the failing depth, for comparing against another engine
+                    for (m, count) in perft.divide(&epd.position, result.depth) {
+                        println!("{m}: {count}");
                     }
-                    _ => (),
+                }
+                Some(result) => {
+                    println!("{fen}: pass to depth {}", result.depth);
+                    passed += 1;
+                }
+                None => {
+                    println!("{fen}: no perft counts");
+                    passed += 1;
                 }
             }
         }
+
+        println!("Passed {passed} of {total} positions");
     }
 
-    pub fn optionarg<'a>(s: &str, it: &mut impl Iterator<Item = &'a str>) -> Option<&'a str> {
-        if let Some(what) = it.next() {
-            if what == s {
-                return it.next();
+    // Fixed depth searches from a cleared TT, so with one thread the total node
+    // count only changes when the search does
+    pub fn bench(&mut self, depth: usize, hash: usize, threads: usize) -> usize {
+        let saved = (
+            self.position.clone(),
+            self.options.threads,
+            self.options.ownbook,
+            self.options.multipv,
+        );
+        let limits = SearchLimits::parse(&["depth", &depth.to_string()]).unwrap();
+        let start = Instant::now();
+        let mut total = 0;
+
+        let tt = match TranspositionTable::new(hash) {
+            Ok(tt) => tt,
+            Err(err) => {
+                println!("info string cannot allocate {hash} MB hash: {err}");
+                return 0;
             }
+        };
+
+        // The user's table is kept aside, it may have been loaded from a file
+        let tt = mem::replace(&mut self.tt, tt);
+
+        self.options.threads = threads;
+        self.options.ownbook = false;
+        self.options.multipv = 1;
+        self.stop.store(false, Ordering::Relaxed);
+
+        for (index, fen) in bench::POSITIONS.iter().enumerate() {
+            self.position = Position::from_str(fen);
+            self.tt.clear();
+
+            let nodes = search::search(self, &limits).nodes;
+
+            println!(
+                "Position {}/{}: {fen} nodes {nodes}",
+                index + 1,
+                bench::POSITIONS.len()
+            );
+
+            total += nodes;
         }
 
-        None
+        let elapsed = start.elapsed();
+        let nps = (total as f64 / elapsed.as_secs_f64().max(0.001)) as usize;
+
+        println!("Total nodes {total} time {} nps {nps}", elapsed.as_millis());
+
+        (
+            self.position,
+            self.options.threads,
+            self.options.ownbook,
+            self.options.multipv,
+        ) = saved;
+        self.tt = tt;
+
+        total
+    }
+
+    pub fn setoption(&mut self, name: &str, value: &str) {
+        match self.options.set(name, value) {
+            Ok("Hash") => match TranspositionTable::new(self.options.hash) {
+                Ok(tt) => self.tt = tt,
+                Err(err) => {
+                    println!(
+                        "info string cannot allocate {} MB hash: {err}",
+                        self.options.hash
+                    );
+
+                    self.options.hash = self.tt.megabytes();
+                }
+            },
+            Ok("Clear Hash") => self.tt.clear(),
+            Ok("Save Hash to File") => {
+                let path = Path::new(&self.options.hash_file);
+
+                match self.tt.save(path, self.age) {
+                    Ok(()) => println!("info string saved hash to {}", path.display()),
+                    Err(err) => println!("info string cannot save {}: {err}", path.display()),
+                }
+            }
+            Ok("Load Hash from File") => {
+                let path = Path::new(&self.options.hash_file);
+
+                match TranspositionTable::load(path) {
+                    Ok((tt, age)) => {
+                        self.options.hash = tt.megabytes();
+                        self.tt = tt;
+                        self.age = age;
+
+                        println!("info string loaded hash from {}", path.display());
+                    }
+                    Err(err) => println!("info string cannot load {}: {err}", path.display()),
+                }
+            }
+            Ok("Seed") => self.rng = Mutex::new(Self::seeded_rng(self.options.seed)),
+            Ok(_) => (),
+            Err(err) => println!("info string {err}"),
+        }
     }
 }
@@ -0,0 +1,371 @@
+use std::fmt;
+
+use crate::{
+    position::{FenError, Position},
+    r#move::Move,
+    san::SanError,
+};
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum EpdError {
+    Fen(FenError),
+    San(String, SanError),
+    Operand(String, String),
+    Unterminated(String),
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Operand {
+    Moves(Vec<Move>),
+    String(String),
+    Integer(i64),
+    Other(Vec<String>),
+}
+
+// Operations keep the order they were read or set in
+#[derive(Clone)]
+pub struct Epd {
+    pub position: Position,
+    pub operations: Vec<(String, Operand)>,
+}
+
+impl fmt::Display for EpdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fen(err) => write!(f, "{err}"),
+            Self::San(opcode, err) => write!(f, "{err} in {opcode}"),
+            Self::Operand(opcode, operand) => write!(f, "bad operand {operand} for {opcode}"),
+            Self::Unterminated(opcode) => write!(f, "unterminated string in {opcode}"),
+        }
+    }
+}
+
+impl Epd {
+    pub fn new(position: Position) -> Self {
+        Self {
+            position,
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, EpdError> {
+        let fields: Vec<_> = line.split_whitespace().take(6).collect();
+        let digits = |field: &str| field.bytes().all(|byte| byte.is_ascii_digit());
+
+        // Perft suites often give full FENs, with the clocks before the operations
+        let count = match fields[..] {
+            [_, _, _, _, halfmove, fullmove] if digits(halfmove) && digits(fullmove) => 6,
+            _ => fields.len().min(4),
+        };
+
+        let mut rest = line;
+
+        for _ in 0..count {
+            rest = rest.trim_start();
+            rest = &rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..];
+        }
+
+        let operations = Self::parse_operations(rest)?;
+        let mut fen: Vec<_> = fields[..count]
+            .iter()
+            .map(|field| field.to_string())
+            .collect();
+
+        if fen.len() == 4 {
+            fen.extend(["0".to_string(), "1".to_string()]);
+        }
+
+        // hmvc and fmvn override the clocks
+        for (index, opcode) in [(4, "hmvc"), (5, "fmvn")] {
+            if let Some((_, operands)) = operations.iter().find(|(op, _)| op == opcode) {
+                if let ([clock], Some(field)) = (&operands[..], fen.get_mut(index)) {
+                    *field = clock.clone();
+                }
+            }
+        }
+
+        let fen: Vec<_> = fen.iter().map(String::as_str).collect();
+        let position = Position::from_fields(&fen).map_err(EpdError::Fen)?;
+        let mut epd = Self::new(position);
+
+        for (opcode, operands) in operations {
+            let operand = epd.parse_operand(&opcode, operands)?;
+
+            epd.operations.push((opcode, operand));
+        }
+
+        Ok(epd)
+    }
+
+    // Splits into opcodes and raw operands, strings keep their quotes
+    fn parse_operations(mut rest: &str) -> Result<Vec<(String, Vec<String>)>, EpdError> {
+        let mut operations = Vec::new();
+
+        loop {
+            rest = rest.trim_start_matches(|ch: char| ch.is_whitespace() || ch == ';');
+
+            let Some(end) = rest
+                .find(|ch: char| ch.is_whitespace() || ch == ';')
+                .or((!rest.is_empty()).then_some(rest.len()))
+            else {
+                return Ok(operations);
+            };
+
+            let opcode = rest[..end].to_string();
+            let mut operands = Vec::new();
+
+            rest = &rest[end..];
+
+            loop {
+                rest = rest.trim_start();
+
+                if rest.is_empty() || rest.starts_with(';') {
+                    break;
+                }
+
+                let end = if let Some(string) = rest.strip_prefix('"') {
+                    match string.find('"') {
+                        Some(index) => index + 2,
+                        None => return Err(EpdError::Unterminated(opcode)),
+                    }
+                } else {
+                    rest.find(|ch: char| ch.is_whitespace() || ch == ';')
+                        .unwrap_or(rest.len())
+                };
+
+                operands.push(rest[..end].to_string());
+                rest = &rest[end..];
+            }
+
+            operations.push((opcode, operands));
+        }
+    }
+
+    fn parse_operand(&self, opcode: &str, operands: Vec<String>) -> Result<Operand, EpdError> {
+        let bad = |operands: &[String]| EpdError::Operand(opcode.to_string(), operands.join(" "));
+
+        match opcode {
+            "bm" | "am" => operands
+                .iter()
+                .map(|san| {
+                    self.position
+                        .parse_san(san)
+                        .map_err(|err| EpdError::San(opcode.to_string(), err))
+                })
+                .collect::<Result<_, _>>()
+                .map(Operand::Moves),
+            "id" | "c0" | "c1" | "c2" | "c3" | "c4" | "c5" | "c6" | "c7" | "c8" | "c9" => {
+                match &operands[..] {
+                    [s] => match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                        Some(s) => Ok(Operand::String(s.to_string())),
+                        None => Err(bad(&operands)),
+                    },
+                    _ => Err(bad(&operands)),
+                }
+            }
+            "ce" | "acd" | "hmvc" | "fmvn" => match &operands[..] {
+                [n] => n.parse().map(Operand::Integer).map_err(|_| bad(&operands)),
+                _ => Err(bad(&operands)),
+            },
+            _ if Self::perft_depth(opcode).is_some() => match &operands[..] {
+                [n] => n.parse().map(Operand::Integer).map_err(|_| bad(&operands)),
+                _ => Err(bad(&operands)),
+            },
+            _ => Ok(Operand::Other(operands)),
+        }
+    }
+
+    fn perft_depth(opcode: &str) -> Option<usize> {
+        opcode
+            .strip_prefix('D')?
+            .parse()
+            .ok()
+            .filter(|&depth| depth > 0)
+    }
+
+    pub fn get(&self, opcode: &str) -> Option<&Operand> {
+        self.operations
+            .iter()
+            .find(|(op, _)| op == opcode)
+            .map(|(_, operand)| operand)
+    }
+
+    pub fn set(&mut self, opcode: &str, operand: Operand) {
+        match self.operations.iter_mut().find(|(op, _)| op == opcode) {
+            Some((_, old)) => *old = operand,
+            None => self.operations.push((opcode.to_string(), operand)),
+        }
+    }
+
+    pub fn best_moves(&self) -> &[Move] {
+        match self.get("bm") {
+            Some(Operand::Moves(moves)) => moves,
+            _ => &[],
+        }
+    }
+
+    pub fn avoid_moves(&self) -> &[Move] {
+        match self.get("am") {
+            Some(Operand::Moves(moves)) => moves,
+            _ => &[],
+        }
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.string("id")
+    }
+
+    pub fn comment(&self, index: usize) -> Option<&str> {
+        self.string(&format!("c{index}"))
+    }
+
+    pub fn eval(&self) -> Option<i64> {
+        self.integer("ce")
+    }
+
+    pub fn depth(&self) -> Option<i64> {
+        self.integer("acd")
+    }
+
+    // Expected perft counts, sorted by depth
+    pub fn perft(&self) -> Vec<(usize, u64)> {
+        let mut counts: Vec<_> = self
+            .operations
+            .iter()
+            .filter_map(|(opcode, operand)| match operand {
+                Operand::Integer(count) => Some((Self::perft_depth(opcode)?, *count as u64)),
+                _ => None,
+            })
+            .collect();
+
+        counts.sort();
+        counts
+    }
+
+    // Records a search result as bm, ce and acd
+    pub fn set_analysis(&mut self, best: Move, score: i64, depth: usize) {
+        self.set("bm", Operand::Moves(vec![best]));
+        self.set("ce", Operand::Integer(score));
+        self.set("acd", Operand::Integer(depth as i64));
+    }
+
+    fn string(&self, opcode: &str) -> Option<&str> {
+        match self.get(opcode) {
+            Some(Operand::String(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn integer(&self, opcode: &str) -> Option<i64> {
+        match self.get(opcode) {
+            Some(Operand::Integer(n)) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Epd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fen = self.position.fen();
+        let fields: Vec<_> = fen.split_whitespace().take(4).collect();
+
+        write!(f, "{}", fields.join(" "))?;
+
+        for (opcode, operand) in &self.operations {
+            write!(f, " {opcode}")?;
+
+            match operand {
+                Operand::Moves(moves) => {
+                    for r#move in moves {
+                        write!(f, " {}", self.position.to_san(*r#move))?;
+                    }
+                }
+                Operand::String(s) => write!(f, " \"{s}\"")?,
+                Operand::Integer(n) => write!(f, " {n}")?,
+                Operand::Other(operands) => {
+                    for operand in operands {
+                        write!(f, " {operand}")?;
+                    }
+                }
+            }
+
+            write!(f, ";")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_write() {
+        let line = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - \
+            bm Bb5 Bc4; am Ke2; id \"test; 1\"; c0 \"Ruy Lopez\"; ce 35; acd 12; pv Bb5 a6;";
+        let epd = Epd::parse(line).unwrap();
+
+        assert_eq!(
+            epd.best_moves(),
+            [Move::from_str("f1b5"), Move::from_str("f1c4")]
+        );
+        assert_eq!(epd.avoid_moves(), [Move::from_str("e1e2")]);
+        assert_eq!(epd.id(), Some("test; 1"));
+        assert_eq!(epd.comment(0), Some("Ruy Lopez"));
+        assert_eq!(epd.comment(1), None);
+        assert_eq!(epd.eval(), Some(35));
+        assert_eq!(epd.depth(), Some(12));
+        assert_eq!(
+            epd.to_string(),
+            line.replace("; ", ";").replace(";", "; ").trim_end()
+        );
+
+        let mut epd = Epd::new(Position::new());
+
+        epd.set_analysis(Move::from_str("e2e4"), 20, 10);
+
+        assert_eq!(
+            epd.to_string(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; ce 20; acd 10;"
+        );
+    }
+
+    #[test]
+    fn perft() {
+        let epd = Epd::parse("4k3/8/8/8/8/8/8/4K2R w K - 0 1 ;D1 15 ;D2 66").unwrap();
+
+        assert_eq!(epd.perft(), [(1, 15), (2, 66)]);
+        assert_eq!(epd.to_string(), "4k3/8/8/8/8/8/8/4K2R w K - D1 15; D2 66;");
+
+        let epd = Epd::parse("4k3/8/8/8/8/8/8/4K2R b K - hmvc 7; fmvn 30;").unwrap();
+
+        assert_eq!(epd.position.fen(), "4k3/8/8/8/8/8/8/4K2R b K - 7 30");
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - bm Ke3;",
+                EpdError::San("bm".to_string(), SanError::Illegal("Ke3".to_string())),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - acd x;",
+                EpdError::Operand("acd".to_string(), "x".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - id \"open;",
+                EpdError::Unterminated("id".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w",
+                EpdError::Fen(FenError::FieldCount(2)),
+            ),
+        ];
+
+        for (line, err) in cases {
+            assert_eq!(Epd::parse(line).err(), Some(err), "{line}");
+        }
+    }
+}
@@ -1,19 +1,161 @@
+#[cfg(target_arch = "x86_64")]
 use std::arch::x86_64;
 
 use crate::bitboard::Bitboard;
 use crate::shift;
 use crate::types::{File, Rank, Square};
 
+// Multipliers mapping every blocker subset of a mask to a unique index in
+// the fewest bits, used where pext is not available
+const BISHOP_MAGICS: [u64; 64] = [
+    0x1002200101020088,
+    0x4004010404049080,
+    0x200404008A020422,
+    0x000806004C002202,
+    0x8001104110000090,
+    0x8002080248000202,
+    0x0000440484408000,
+    0x0002010082012020,
+    0x0488406912288201,
+    0x0101600202004110,
+    0x0810100400842440,
+    0x0800240410822002,
+    0x0000020210010030,
+    0x8100008260210008,
+    0x4404004404044201,
+    0x2440008084104200,
+    0x88220A08207C0280,
+    0x4104801001080120,
+    0x4110020808304010,
+    0x2518050402400980,
+    0x0002000422010010,
+    0x0403020080A00100,
+    0x0244200200822880,
+    0x0608200200820860,
+    0x81202818C6100400,
+    0x4081501088100100,
+    0x2002491448020400,
+    0x0070040000440008,
+    0x0880820004010400,
+    0x8490108202C80400,
+    0x0004004204050C41,
+    0x800102400C241402,
+    0x1014200A00041013,
+    0x0008110400300400,
+    0x0042060900220800,
+    0x00D2200800010105,
+    0x0C0A0484008A0020,
+    0x801001010802100C,
+    0x0919920202440101,
+    0x06020A0202604050,
+    0x4202083240260821,
+    0x0118520260221008,
+    0x00000C4402021001,
+    0x1010006018020100,
+    0x9600204C10130500,
+    0x00C0810403000020,
+    0x0002640420801401,
+    0x8001040400900840,
+    0x1002221004048000,
+    0x0020808808420401,
+    0x0100010080908040,
+    0x2000800884040820,
+    0x1000002020444B28,
+    0x01180890D0008888,
+    0x4090448800940100,
+    0x0160380A00404240,
+    0x5002042109101080,
+    0x800800220D300800,
+    0x0808200840441004,
+    0x8008022006150402,
+    0x2000000070020213,
+    0x0202414119211100,
+    0x8820620204180484,
+    0x8012100115010208,
+];
+
+const ROOK_MAGICS: [u64; 64] = [
+    0x0880102080004000,
+    0x8340004410002000,
+    0x0880200090008268,
+    0x0080080080100004,
+    0x8100110004020800,
+    0x0300010004000822,
+    0x08801A0029000080,
+    0x8100050001204882,
+    0x0844800081400320,
+    0x0804402010004000,
+    0x0108802003100480,
+    0x0004808008001000,
+    0x0003001801001014,
+    0x0002000200041008,
+    0x0004008108042210,
+    0x0105000100009042,
+    0x0400808000400021,
+    0xC100404010002000,
+    0x0060008010002088,
+    0x0400808008001000,
+    0x4440808008000400,
+    0x1002008004000280,
+    0x40024400300D1248,
+    0x0010020000408104,
+    0x0101008200204200,
+    0x8020002040005000,
+    0x4100100080802000,
+    0x4008006A80100280,
+    0x1020080080040080,
+    0x0004010040020040,
+    0x0018A12400080290,
+    0x6140004200008104,
+    0x4000400020800090,
+    0x2020002080804000,
+    0x0000408202002010,
+    0x0080100501000820,
+    0x0000800400800800,
+    0x000A200408014010,
+    0x0100800200800100,
+    0xA00800570200008C,
+    0x008000406000C010,
+    0x1040100028002000,
+    0x0048200100110040,
+    0x0068490210030020,
+    0x1009080005010010,
+    0x2142000804010100,
+    0x1001080110840002,
+    0x1801004400820001,
+    0x010440208D020200,
+    0x0000400020008080,
+    0x0200200080100280,
+    0x0000100020090100,
+    0x0204008008020480,
+    0x8104010040020040,
+    0x78000201B0080400,
+    0x0040800051002880,
+    0x0050108001002041,
+    0x208A801100614003,
+    0x0006002042089082,
+    0x0011090004201001,
+    0x1002001004200802,
+    0x0005000208040001,
+    0x0002002701AC0822,
+    0x000010250184004A,
+];
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct Magic {
     index: usize,
     mask: Bitboard,
+    magic: u64,
+    shift: u32,
 }
 
+// Attack lookup either with BMI2 pext, or with magic multiplication when the
+// CPU does not have it
 pub struct MagicTable {
     bitboards: Vec<Bitboard>,
     bishop: [Magic; 64],
     rook: [Magic; 64],
+    pext: bool,
 }
 
 impl Magic {
@@ -21,6 +163,8 @@ impl Magic {
         bitboards: &mut Vec<Bitboard>,
         square: Square,
         ray: fn(Bitboard, Bitboard) -> Bitboard,
+        magic: u64,
+        pext: bool,
     ) -> Self {
         let h =
             (Bitboard::from(File::A) | Bitboard::from(File::H)) & !Bitboard::from(square.file());
@@ -30,65 +174,156 @@ impl Magic {
 
         let mask = (ray)(square.into(), !Bitboard(0)) & !(h | v);
         let index = bitboards.len();
+        let bits = mask.0.count_ones();
+
+        let magic = Self {
+            index,
+            mask,
+            magic,
+            shift: 64 - bits,
+        };
+
+        bitboards.resize(index + (1 << bits), Bitboard(0));
 
+        // Subsets are enumerated in the order pext numbers them
         let mut bitboard = Bitboard(0);
+        let mut count = 0;
 
         loop {
-            bitboards.push((ray)(square.into(), !bitboard));
+            let offset = if pext { count } else { magic.offset(bitboard) };
+
+            bitboards[index + offset] = (ray)(square.into(), !bitboard);
             bitboard = (bitboard - mask) & mask;
+            count += 1;
 
             if bitboard == Bitboard(0) {
                 break;
             }
         }
 
-        Self { mask, index }
+        magic
+    }
+
+    fn offset(&self, occupied: Bitboard) -> usize {
+        ((occupied.0 & self.mask.0).wrapping_mul(self.magic) >> self.shift) as usize
     }
 }
 
 impl MagicTable {
     pub fn new() -> Self {
+        #[cfg(all(target_arch = "x86_64", not(feature = "no-pext")))]
+        let pext = is_x86_feature_detected!("bmi2");
+
+        // The no-pext feature is for CPUs with a slow microcoded pext, like AMD before Zen 3
+        #[cfg(any(not(target_arch = "x86_64"), feature = "no-pext"))]
+        let pext = false;
+
+        Self::with_pext(pext)
+    }
+
+    // Asking for pext on a CPU without BMI2 panics, rather than faulting later
+    pub fn with_pext(pext: bool) -> Self {
+        #[cfg(target_arch = "x86_64")]
+        assert!(!pext || is_x86_feature_detected!("bmi2"), "pext needs BMI2");
+
+        #[cfg(not(target_arch = "x86_64"))]
+        assert!(!pext, "pext needs x86_64");
+
         let mut bitboards = Vec::new();
 
         let mut bishop = [Magic {
             index: 0,
             mask: Bitboard(0),
+            magic: 0,
+            shift: 0,
         }; 64];
 
-        let mut rook = [Magic {
-            index: 0,
-            mask: Bitboard(0),
-        }; 64];
+        let mut rook = bishop;
 
         for square in Square::iter() {
-            *square.index_mut(&mut bishop) = Magic::new(&mut bitboards, square, shift::bishop_ray);
-            *square.index_mut(&mut rook) = Magic::new(&mut bitboards, square, shift::rook_ray);
+            *square.index_mut(&mut bishop) = Magic::new(
+                &mut bitboards,
+                square,
+                shift::bishop_ray,
+                *square.index(&BISHOP_MAGICS),
+                pext,
+            );
+            *square.index_mut(&mut rook) = Magic::new(
+                &mut bitboards,
+                square,
+                shift::rook_ray,
+                *square.index(&ROOK_MAGICS),
+                pext,
+            );
         }
 
         Self {
             bitboards,
             bishop,
             rook,
+            pext,
         }
     }
 
     pub fn bishop(&self, square: Square, occupied: Bitboard) -> Bitboard {
-        let magic = square.index(&self.bishop);
+        self.attacks(square.index(&self.bishop), occupied)
+    }
+
+    pub fn rook(&self, square: Square, occupied: Bitboard) -> Bitboard {
+        self.attacks(square.index(&self.rook), occupied)
+    }
 
-        unsafe {
-            let index = x86_64::_pext_u64(occupied.0, magic.mask.0) as usize;
+    #[inline(always)]
+    fn attacks(&self, magic: &Magic, occupied: Bitboard) -> Bitboard {
+        let offset = match self.pext {
+            // Only set when the CPU was detected to support BMI2
+            #[cfg(target_arch = "x86_64")]
+            true => unsafe { pext(occupied.0, magic.mask.0) as usize },
+            _ => magic.offset(occupied),
+        };
 
-            *self.bitboards.get_unchecked(magic.index + index)
-        }
+        unsafe { *self.bitboards.get_unchecked(magic.index + offset) }
     }
+}
 
-    pub fn rook(&self, square: Square, occupied: Bitboard) -> Bitboard {
-        let magic = square.index(&self.rook);
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "bmi2")]
+unsafe fn pext(value: u64, mask: u64) -> u64 {
+    x86_64::_pext_u64(value, mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
-        unsafe {
-            let index = x86_64::_pext_u64(occupied.0, magic.mask.0) as usize;
+    #[test]
+    fn backends_match_rays() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut tables = vec![MagicTable::with_pext(false)];
+
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("bmi2") {
+            tables.push(MagicTable::with_pext(true));
+        }
 
-            *self.bitboards.get_unchecked(magic.index + index)
+        for _ in 0..1000 {
+            // Sparse and dense boards both
+            let occupied = match rng.random_range(0..3) {
+                0 => Bitboard(rng.random::<u64>() & rng.random::<u64>()),
+                1 => Bitboard(rng.random()),
+                _ => Bitboard(rng.random::<u64>() | rng.random::<u64>()),
+            };
+
+            for square in Square::iter() {
+                let bishop = shift::bishop_ray(square.into(), !occupied);
+                let rook = shift::rook_ray(square.into(), !occupied);
+
+                for table in &tables {
+                    assert_eq!(table.bishop(square, occupied), bishop, "{square:?}");
+                    assert_eq!(table.rook(square, occupied), rook, "{square:?}");
+                }
+            }
         }
     }
 }
@@ -1,27 +1,33 @@
 use engine::Engine;
 
 pub mod attack;
+pub mod bench;
 pub mod bitboard;
 pub mod board;
 pub mod book;
 pub mod engine;
+pub mod epd;
 pub mod gen;
 pub mod global;
 pub mod magic;
 pub mod r#move;
 pub mod options;
+pub mod perft;
+pub mod pgn;
 pub mod pick;
 pub mod piecesquaretable;
 pub mod position;
+pub mod san;
 pub mod search;
 pub mod searchlimits;
 pub mod shift;
 pub mod tt;
 pub mod types;
+pub mod uci;
 pub mod zobrist;
 
 fn main() {
-    let mut engine = Engine::new();
+    let engine = Engine::new();
 
     engine.run();
 }
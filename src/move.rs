@@ -18,6 +18,14 @@ impl Move {
         Self(from as u16 | (to as u16) << 6 | (kind as u16) << 12)
     }
 
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
     pub fn from(self) -> Square {
         unsafe { mem::transmute((self.0 & 63) as u8) }
     }
@@ -43,6 +51,27 @@ impl Move {
             Self::new(from, to)
         }
     }
+
+    // Long algebraic notation, not checked for legality
+    pub fn try_from_str(s: &str) -> Option<Self> {
+        if !s.is_ascii() {
+            return None;
+        }
+
+        let from = Square::try_from_str(s.get(0..2)?)?;
+        let to = Square::try_from_str(s.get(2..4)?)?;
+
+        match s.as_bytes()[4..] {
+            [] => Some(Self::new(from, to)),
+            [ch] => match Kind::try_from_char(ch as char)? {
+                kind @ (Kind::Knight | Kind::Bishop | Kind::Rook | Kind::Queen) => {
+                    Some(Self::new_promotion(from, to, kind))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Move {
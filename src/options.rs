@@ -1,14 +1,195 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::tt::TranspositionTable;
+
+pub enum OptionKind {
+    Check { default: bool },
+    Spin { default: i64, min: i64, max: i64 },
+    Button,
+    String { default: &'static str },
+}
+
+pub enum OptionValue {
+    Check(bool),
+    Spin(i64),
+    Button,
+    String(String),
+}
+
+pub struct UciOption {
+    pub name: &'static str,
+    pub kind: OptionKind,
+}
+
 pub struct Options {
-    pub debug: bool,
+    pub hash: usize,
+    pub threads: usize,
+    pub move_overhead: Duration,
     pub ownbook: bool,
+    pub multipv: usize,
+    pub perft_hash: usize,
+    pub hash_file: String,
+    pub seed: u64,
+}
+
+impl OptionKind {
+    pub fn parse(&self, value: &str) -> Result<OptionValue, String> {
+        match *self {
+            Self::Check { .. } => match value {
+                "true" => Ok(OptionValue::Check(true)),
+                "false" => Ok(OptionValue::Check(false)),
+                _ => Err(format!("expected true or false, got {value}")),
+            },
+            Self::Spin { min, max, .. } => match value.parse() {
+                Ok(value) if (min..=max).contains(&value) => Ok(OptionValue::Spin(value)),
+                _ => Err(format!(
+                    "expected a number from {min} to {max}, got {value}"
+                )),
+            },
+            Self::Button => Ok(OptionValue::Button),
+            Self::String { .. } => Ok(OptionValue::String(value.to_string())),
+        }
+    }
 }
 
 impl Options {
+    pub const DECLARED: [UciOption; 11] = [
+        UciOption {
+            name: "Hash",
+            kind: OptionKind::Spin {
+                default: 32,
+                min: 1,
+                max: TranspositionTable::MAX_MEGABYTES as i64,
+            },
+        },
+        UciOption {
+            name: "Clear Hash",
+            kind: OptionKind::Button,
+        },
+        UciOption {
+            name: "Hash File",
+            kind: OptionKind::String {
+                default: "hash.bin",
+            },
+        },
+        UciOption {
+            name: "Save Hash to File",
+            kind: OptionKind::Button,
+        },
+        UciOption {
+            name: "Load Hash from File",
+            kind: OptionKind::Button,
+        },
+        UciOption {
+            name: "Threads",
+            kind: OptionKind::Spin {
+                default: 1,
+                min: 1,
+                max: 1024,
+            },
+        },
+        UciOption {
+            name: "Move Overhead",
+            kind: OptionKind::Spin {
+                default: 10,
+                min: 0,
+                max: 5000,
+            },
+        },
+        UciOption {
+            name: "OwnBook",
+            kind: OptionKind::Check { default: true },
+        },
+        UciOption {
+            name: "MultiPV",
+            kind: OptionKind::Spin {
+                default: 1,
+                min: 1,
+                max: 218,
+            },
+        },
+        UciOption {
+            name: "Perft Hash",
+            kind: OptionKind::Spin {
+                default: 0,
+                min: 0,
+                max: 65536,
+            },
+        },
+        UciOption {
+            name: "Seed",
+            kind: OptionKind::Spin {
+                default: 1,
+                min: 0,
+                max: i64::MAX,
+            },
+        },
+    ];
+
     pub fn new() -> Self {
         Self {
-            debug: false,
+            hash: 32,
+            threads: 1,
+            move_overhead: Duration::from_millis(10),
             ownbook: true,
+            multipv: 1,
+            perft_hash: 0,
+            hash_file: "hash.bin".to_string(),
+            seed: 1,
+        }
+    }
+
+    pub fn declared(name: &str) -> Option<&'static UciOption> {
+        Self::DECLARED
+            .iter()
+            .find(|option| option.name.eq_ignore_ascii_case(name))
+    }
+
+    // Returns the declared name of the option that was changed
+    pub fn set(&mut self, name: &str, value: &str) -> Result<&'static str, String> {
+        let option = Self::declared(name).ok_or_else(|| format!("unknown option {name}"))?;
+
+        let value = option
+            .kind
+            .parse(value)
+            .map_err(|err| format!("bad value for option {}: {err}", option.name))?;
+
+        match (option.name, value) {
+            ("Hash", OptionValue::Spin(value)) => self.hash = value as usize,
+            ("Threads", OptionValue::Spin(value)) => self.threads = value as usize,
+            ("Move Overhead", OptionValue::Spin(value)) => {
+                self.move_overhead = Duration::from_millis(value as u64)
+            }
+            ("OwnBook", OptionValue::Check(value)) => self.ownbook = value,
+            ("MultiPV", OptionValue::Spin(value)) => self.multipv = value as usize,
+            ("Perft Hash", OptionValue::Spin(value)) => self.perft_hash = value as usize,
+            ("Hash File", OptionValue::String(value)) => self.hash_file = value,
+            ("Seed", OptionValue::Spin(value)) => self.seed = value as u64,
+            _ => (),
         }
+
+        Ok(option.name)
     }
 }
 
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for UciOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "option name {} type ", self.name)?;
+
+        match self.kind {
+            OptionKind::Check { default } => write!(f, "check default {default}"),
+            OptionKind::Spin { default, min, max } => {
+                write!(f, "spin default {default} min {min} max {max}")
+            }
+            OptionKind::Button => write!(f, "button"),
+            OptionKind::String { default } => write!(f, "string default {default}"),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+
+use crate::{
+    epd::Epd,
+    gen::{self, MoveVec},
+    position::Position,
+    r#move::Move,
+};
+
+pub struct PerftResult {
+    pub depth: usize,
+    pub expected: u64,
+    pub nodes: usize,
+}
+
+// Node counts are packed with the depth, and the key is xored with the data
+// so torn writes from other threads are detected as misses
+struct PerftEntry {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+pub struct PerftTable {
+    entries: Vec<PerftEntry>,
+}
+
+// Splits the root moves over threads, with an optional shared hash table
+pub struct Perft {
+    threads: usize,
+    table: Option<PerftTable>,
+}
+
+impl PerftResult {
+    pub fn passed(&self) -> bool {
+        self.nodes as u64 == self.expected
+    }
+}
+
+impl PerftTable {
+    pub fn new(megabytes: usize) -> Self {
+        let count = megabytes * 1024 * 1024 / mem::size_of::<PerftEntry>();
+
+        Self {
+            entries: (0..count.max(1))
+                .map(|_| PerftEntry {
+                    key: AtomicU64::new(0),
+                    data: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    fn probe(&self, hash: u64, depth: usize) -> Option<usize> {
+        let entry = &self.entries[hash as usize % self.entries.len()];
+        let data = entry.data.load(Ordering::Relaxed);
+
+        if entry.key.load(Ordering::Relaxed) ^ data == hash && (data & 0xFF) as usize == depth {
+            Some((data >> 8) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn store(&self, hash: u64, depth: usize, nodes: usize) {
+        let entry = &self.entries[hash as usize % self.entries.len()];
+        let data = (nodes as u64) << 8 | depth as u64;
+
+        entry.key.store(hash ^ data, Ordering::Relaxed);
+        entry.data.store(data, Ordering::Relaxed);
+    }
+}
+
+impl Perft {
+    // No table is used when megabytes is 0
+    pub fn new(threads: usize, megabytes: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            table: (megabytes > 0).then(|| PerftTable::new(megabytes)),
+        }
+    }
+
+    pub fn perft(&self, position: &Position, depth: usize) -> usize {
+        match depth {
+            0 => 1,
+            _ => self
+                .divide(position, depth)
+                .iter()
+                .map(|(_, count)| count)
+                .sum(),
+        }
+    }
+
+    // Node counts per root move, in move generation order
+    pub fn divide(&self, position: &Position, depth: usize) -> Vec<(Move, usize)> {
+        let mut moves = MoveVec::new();
+
+        gen::generate_dyn::<true>(&mut moves, position);
+
+        let moves = moves.moves();
+        let counts: Vec<_> = moves.iter().map(|_| AtomicUsize::new(0)).collect();
+        let next = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(moves.len()) {
+                scope.spawn(|| {
+                    let mut position = position.clone();
+
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+
+                        let Some(&m) = moves.get(index) else {
+                            break;
+                        };
+
+                        let undo = position.make(m);
+                        let count =
+                            count(&mut position, depth.saturating_sub(1), self.table.as_ref());
+
+                        counts[index].store(count, Ordering::Relaxed);
+                        position.unmake(undo);
+                    }
+                });
+            }
+        });
+
+        moves
+            .iter()
+            .zip(counts)
+            .map(|(&m, count)| (m, count.into_inner()))
+            .collect()
+    }
+
+    // Checks the D1..Dn counts of a perft EPD, stopping at the first mismatch
+    pub fn run_epd(&self, epd: &Epd, max_depth: usize) -> Vec<PerftResult> {
+        let mut results = Vec::new();
+
+        for (depth, expected) in epd.perft() {
+            if depth > max_depth {
+                break;
+            }
+
+            let nodes = self.perft(&epd.position, depth);
+            let result = PerftResult {
+                depth,
+                expected,
+                nodes,
+            };
+            let passed = result.passed();
+
+            results.push(result);
+
+            if !passed {
+                break;
+            }
+        }
+
+        results
+    }
+}
+
+pub fn perft(position: &mut Position, depth: usize) -> usize {
+    count(position, depth, None)
+}
+
+fn count(position: &mut Position, depth: usize, table: Option<&PerftTable>) -> usize {
+    if depth == 0 {
+        return 1;
+    } else if depth == 1 {
+        let mut total = 0;
+
+        gen::generate_dyn::<true>(&mut total, position);
+
+        return total;
+    }
+
+    if let Some(nodes) = table.and_then(|table| table.probe(position.hash(), depth)) {
+        return nodes;
+    }
+
+    let mut moves = MoveVec::new();
+
+    gen::generate_dyn::<true>(&mut moves, position);
+
+    let mut total = 0;
+
+    for m in moves.moves() {
+        let undo = position.make(*m);
+
+        total += count(position, depth - 1, table);
+
+        position.unmake(undo);
+    }
+
+    if let Some(table) = table {
+        table.store(position.hash(), depth, total);
+    }
+
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUITE: [&str; 6] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 ;D1 20 ;D2 400 ;D3 8902 ;D4 197281",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1 ;D1 48 ;D2 2039 ;D3 97862",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1 ;D1 14 ;D2 191 ;D3 2812 ;D4 43238",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1 ;D1 6 ;D2 264 ;D3 9467 ;D4 422333",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8 ;D1 44 ;D2 1486 ;D3 62379",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10 ;D1 46 ;D2 2079 ;D3 89890",
+    ];
+
+    #[test]
+    fn suite() {
+        for perft in [Perft::new(1, 0), Perft::new(4, 1)] {
+            for line in SUITE {
+                let epd = Epd::parse(line).unwrap();
+                let results = perft.run_epd(&epd, usize::MAX);
+
+                assert_eq!(results.len(), epd.perft().len(), "{line}");
+
+                for result in results {
+                    assert!(
+                        result.passed(),
+                        "{line} depth {} got {}",
+                        result.depth,
+                        result.nodes
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut position = Position::from_str(SUITE[1].split(" ;").next().unwrap());
+        let total: usize = Perft::new(2, 0)
+            .divide(&position, 2)
+            .iter()
+            .map(|(_, count)| count)
+            .sum();
+
+        assert_eq!(total, perft(&mut position, 2));
+    }
+}
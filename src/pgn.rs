@@ -0,0 +1,671 @@
+use std::fmt;
+use std::io::BufRead;
+
+use crate::{position::Position, r#move::Move, types::Color};
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+const SUFFIXES: [&str; 6] = ["!", "?", "!!", "??", "!?", "?!"];
+const LINE_LENGTH: usize = 80;
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PgnError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Variation {
+    pub comment: Option<String>,
+    pub moves: Vec<Node>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub r#move: Move,
+    pub nags: Vec<u8>,
+    pub comment: Option<String>,
+    // Alternatives to this move, played from the position before it
+    pub variations: Vec<Variation>,
+}
+
+#[derive(Clone)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub position: Position,
+    pub mainline: Variation,
+    pub result: String,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Suffix(String),
+    Symbol(String),
+    Period,
+    Open,
+    Close,
+}
+
+struct Lexer<R> {
+    reader: R,
+    buffer: Vec<char>,
+    index: usize,
+    line: usize,
+    peeked: Option<(usize, Token)>,
+    failed: bool,
+}
+
+// Reads games one at a time, so arbitrarily large files can be streamed
+pub struct PgnReader<R> {
+    lexer: Lexer<R>,
+}
+
+impl PgnError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Node {
+    pub fn new(r#move: Move) -> Self {
+        Self {
+            r#move,
+            nags: Vec::new(),
+            comment: None,
+            variations: Vec::new(),
+        }
+    }
+}
+
+impl Game {
+    pub fn new(position: Position) -> Self {
+        let mut game = Self {
+            tags: Vec::new(),
+            position,
+            mainline: Variation::default(),
+            result: "*".to_string(),
+        };
+
+        for (name, value) in [
+            ("Event", "?"),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", "?"),
+            ("White", "?"),
+            ("Black", "?"),
+            ("Result", "*"),
+        ] {
+            game.set_tag(name, value);
+        }
+
+        game
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+        self.set_tag("Result", result);
+    }
+
+    // Position at the end of the main line
+    pub fn end_position(&self) -> Position {
+        let mut position = self.position.clone();
+
+        for node in &self.mainline.moves {
+            position.make(node.r#move);
+        }
+
+        position
+    }
+
+    fn write_variation(words: &mut Vec<String>, mut position: Position, variation: &Variation) {
+        let mut number = true;
+
+        if let Some(comment) = &variation.comment {
+            Self::write_comment(words, comment);
+        }
+
+        for node in &variation.moves {
+            let fullmove = position.ply() / 2 + 1;
+
+            if position.turn() == Color::White {
+                words.push(format!("{fullmove}."));
+            } else if number {
+                words.push(format!("{fullmove}..."));
+            }
+
+            words.push(position.to_san(node.r#move));
+            words.extend(node.nags.iter().map(|nag| format!("${nag}")));
+
+            if let Some(comment) = &node.comment {
+                Self::write_comment(words, comment);
+            }
+
+            for variation in node.variations.iter().filter(|v| !v.moves.is_empty()) {
+                let start = words.len();
+
+                Self::write_variation(words, position.clone(), variation);
+
+                words[start].insert(0, '(');
+                words.last_mut().unwrap().push(')');
+            }
+
+            number = node.comment.is_some() || !node.variations.is_empty();
+            position.make(node.r#move);
+        }
+    }
+
+    fn write_comment(words: &mut Vec<String>, comment: &str) {
+        let start = words.len();
+
+        words.extend(comment.split_whitespace().map(str::to_string));
+
+        if words.len() == start {
+            words.push(String::new());
+        }
+
+        words[start].insert(0, '{');
+        words.last_mut().unwrap().push('}');
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+
+        let fen = self.position.fen();
+
+        if self.tag("FEN").is_none() && fen != Position::STARTPOS {
+            writeln!(f, "[SetUp \"1\"]")?;
+            writeln!(f, "[FEN \"{fen}\"]")?;
+        }
+
+        writeln!(f)?;
+
+        let mut words = Vec::new();
+
+        Self::write_variation(&mut words, self.position.clone(), &self.mainline);
+        words.push(self.result.clone());
+
+        let mut length = 0;
+
+        for word in words {
+            if length > 0 && length + 1 + word.len() > LINE_LENGTH {
+                writeln!(f)?;
+                length = 0;
+            } else if length > 0 {
+                write!(f, " ")?;
+                length += 1;
+            }
+
+            write!(f, "{word}")?;
+            length += word.len();
+        }
+
+        writeln!(f)
+    }
+}
+
+impl<R: BufRead> Lexer<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: Vec::new(),
+            index: 0,
+            line: 0,
+            peeked: None,
+            failed: false,
+        }
+    }
+
+    fn peek_char(&mut self) -> Result<Option<char>, PgnError> {
+        while self.index >= self.buffer.len() {
+            let mut line = String::new();
+
+            // Reading stops at the first IO error
+            if self.failed {
+                return Ok(None);
+            }
+
+            let bytes = self.reader.read_line(&mut line).map_err(|err| {
+                self.failed = true;
+
+                PgnError::new(self.line + 1, err.to_string())
+            })?;
+
+            if bytes == 0 {
+                return Ok(None);
+            }
+
+            self.line += 1;
+            self.index = 0;
+
+            // Lines starting with % are escaped
+            self.buffer = match line.starts_with('%') {
+                true => Vec::new(),
+                false => line.chars().collect(),
+            };
+        }
+
+        Ok(Some(self.buffer[self.index]))
+    }
+
+    fn next_char(&mut self) -> Result<Option<char>, PgnError> {
+        let ch = self.peek_char()?;
+
+        self.index += 1;
+
+        Ok(ch)
+    }
+
+    fn skip_line(&mut self) {
+        self.index = self.buffer.len();
+    }
+
+    fn peek(&mut self) -> Result<Option<&(usize, Token)>, PgnError> {
+        if self.peeked.is_none() {
+            self.peeked = self.read()?;
+        }
+
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<(usize, Token)>, PgnError> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.read(),
+        }
+    }
+
+    fn read(&mut self) -> Result<Option<(usize, Token)>, PgnError> {
+        while let Some(ch) = self.peek_char()? {
+            if !ch.is_whitespace() {
+                break;
+            }
+
+            self.index += 1;
+        }
+
+        let line = self.line;
+
+        let Some(ch) = self.next_char()? else {
+            return Ok(None);
+        };
+
+        let token = match ch {
+            '[' => self.read_tag()?,
+            '{' => {
+                let mut comment = String::new();
+
+                loop {
+                    match self.next_char()? {
+                        Some('}') => break,
+                        Some(ch) => comment.push(ch),
+                        None => return Err(PgnError::new(line, "unterminated comment")),
+                    }
+                }
+
+                Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" "))
+            }
+            ';' => {
+                let comment: String = self.buffer[self.index..].iter().collect();
+
+                self.skip_line();
+
+                Token::Comment(comment.trim().to_string())
+            }
+            '$' => {
+                let digits = self.read_while(|ch| ch.is_ascii_digit())?;
+
+                match digits.parse() {
+                    Ok(nag) => Token::Nag(nag),
+                    Err(_) => return Err(PgnError::new(line, format!("bad NAG ${digits}"))),
+                }
+            }
+            '!' | '?' => {
+                let suffix = self.read_while(|ch| ch == '!' || ch == '?')?;
+
+                Token::Suffix(format!("{ch}{suffix}"))
+            }
+            '.' => Token::Period,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '*' => Token::Symbol("*".to_string()),
+            ch if ch.is_ascii_alphanumeric() => {
+                let symbol =
+                    self.read_while(|ch| ch.is_ascii_alphanumeric() || "_+#=:-/".contains(ch))?;
+
+                Token::Symbol(format!("{ch}{symbol}"))
+            }
+            ch => {
+                self.skip_line();
+
+                return Err(PgnError::new(line, format!("unexpected character {ch}")));
+            }
+        };
+
+        Ok(Some((line, token)))
+    }
+
+    fn read_while(&mut self, predicate: impl Fn(char) -> bool) -> Result<String, PgnError> {
+        let mut s = String::new();
+
+        while let Some(ch) = self.peek_char()? {
+            if !predicate(ch) {
+                break;
+            }
+
+            s.push(ch);
+            self.index += 1;
+        }
+
+        Ok(s)
+    }
+
+    fn read_tag(&mut self) -> Result<Token, PgnError> {
+        let line = self.line;
+        let error = |lexer: &mut Self| {
+            lexer.skip_line();
+
+            Err(PgnError::new(line, "bad tag pair"))
+        };
+
+        self.read_while(|ch| ch == ' ')?;
+
+        let name = self.read_while(|ch| ch.is_ascii_alphanumeric() || ch == '_')?;
+
+        self.read_while(|ch| ch == ' ')?;
+
+        if name.is_empty() || self.next_char()? != Some('"') {
+            return error(self);
+        }
+
+        let mut value = String::new();
+
+        // Tag pairs never span lines
+        while self.line == line {
+            match self.next_char()? {
+                Some('"') => break,
+                Some('\\') => match self.next_char()? {
+                    Some(ch @ ('\\' | '"')) => value.push(ch),
+                    _ => return error(self),
+                },
+                Some('\n') | None => return error(self),
+                Some(ch) => value.push(ch),
+            }
+        }
+
+        self.read_while(|ch| ch == ' ')?;
+
+        if self.next_char()? != Some(']') {
+            return error(self);
+        }
+
+        Ok(Token::Tag(name, value))
+    }
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lexer: Lexer::new(reader),
+        }
+    }
+
+    fn read_game(&mut self) -> Result<Option<Game>, PgnError> {
+        let mut tags = Vec::new();
+        let mut line = self.lexer.line;
+
+        while let Some((_, Token::Tag(..))) = self.lexer.peek()? {
+            if let Some((start, Token::Tag(name, value))) = self.lexer.next()? {
+                line = start;
+                tags.push((name, value));
+            }
+        }
+
+        if tags.is_empty() && self.lexer.peek()?.is_none() {
+            return Ok(None);
+        }
+
+        let position = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => Position::from_fen(fen)
+                .map_err(|err| PgnError::new(line, format!("bad FEN tag {fen}: {err}")))?,
+            None => Position::new(),
+        };
+
+        let mut game = Game {
+            tags,
+            position: position.clone(),
+            mainline: Variation::default(),
+            result: "*".to_string(),
+        };
+
+        game.result = self.read_variation(position, &mut game.mainline, 0)?;
+
+        Ok(Some(game))
+    }
+
+    // Returns the game result for the main line
+    fn read_variation(
+        &mut self,
+        mut position: Position,
+        variation: &mut Variation,
+        depth: usize,
+    ) -> Result<String, PgnError> {
+        let mut previous = position.clone();
+
+        loop {
+            let Some((line, token)) = self.lexer.next()? else {
+                return match depth {
+                    0 => Ok("*".to_string()),
+                    _ => Err(PgnError::new(self.lexer.line, "unterminated variation")),
+                };
+            };
+
+            let last = variation.moves.last_mut();
+
+            match token {
+                Token::Symbol(result) if RESULTS.contains(&result.as_str()) => {
+                    return match depth {
+                        0 => Ok(result),
+                        _ => Err(PgnError::new(line, "game result inside a variation")),
+                    };
+                }
+                Token::Symbol(number) if number.bytes().all(|byte| byte.is_ascii_digit()) => (),
+                Token::Period => (),
+                Token::Symbol(san) => {
+                    let r#move = position
+                        .parse_san(&san)
+                        .map_err(|err| PgnError::new(line, err.to_string()))?;
+
+                    previous = position.clone();
+                    position.make(r#move);
+                    variation.moves.push(Node::new(r#move));
+                }
+                Token::Nag(nag) => match last {
+                    Some(node) => node.nags.push(nag),
+                    None => return Err(PgnError::new(line, "NAG before any move")),
+                },
+                Token::Suffix(suffix) => match (last, SUFFIXES.iter().position(|&s| s == suffix)) {
+                    (Some(node), Some(index)) => node.nags.push(index as u8 + 1),
+                    _ => return Err(PgnError::new(line, format!("unexpected {suffix}"))),
+                },
+                Token::Comment(comment) => {
+                    let old = match last {
+                        Some(node) => &mut node.comment,
+                        None => &mut variation.comment,
+                    };
+
+                    *old = Some(match old.take() {
+                        Some(old) => format!("{old} {comment}"),
+                        None => comment,
+                    });
+                }
+                Token::Open => {
+                    let Some(node) = last else {
+                        return Err(PgnError::new(line, "variation before any move"));
+                    };
+
+                    let mut alternative = Variation::default();
+
+                    self.read_variation(previous.clone(), &mut alternative, depth + 1)?;
+                    node.variations.push(alternative);
+                }
+                Token::Close => {
+                    return match depth {
+                        0 => Err(PgnError::new(line, "unmatched )")),
+                        _ => Ok(String::new()),
+                    };
+                }
+                // A new game started without a result
+                token @ Token::Tag(..) if depth == 0 => {
+                    self.lexer.peeked = Some((line, token));
+
+                    return Ok("*".to_string());
+                }
+                Token::Tag(..) => return Err(PgnError::new(line, "tag pair inside a variation")),
+            }
+        }
+    }
+
+    // Skips the rest of a broken game
+    fn recover(&mut self) {
+        loop {
+            match self.lexer.peek() {
+                Ok(Some((_, Token::Tag(..)))) | Ok(None) | Err(_) => break,
+                Ok(Some(_)) => self.lexer.peeked = None,
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<Game, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_game() {
+            Ok(game) => game.map(Ok),
+            Err(err) => {
+                self.recover();
+
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PGN: &str = r#"[Event "Test \"quoted\""]
+[Site "?"]
+[Result "1-0"]
+
+{Opening comment} 1. e4 e5 $1 {Good} (1... c5 2. Nf3 (2. c3) d6) 2. Nf3!? Nc6
+3. Bb5 a6 ; rest of line
+4. Ba4 1-0
+
+[Event "Second"]
+[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1"]
+
+1... Kd7 2. e4 *
+"#;
+
+    #[test]
+    fn read_write() {
+        let games: Vec<_> = PgnReader::new(PGN.as_bytes()).map(Result::unwrap).collect();
+
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].tag("Event"), Some("Test \"quoted\""));
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[0].mainline.moves.len(), 7);
+        assert_eq!(games[0].mainline.moves[1].nags, [1]);
+        assert_eq!(games[0].mainline.moves[2].nags, [5]);
+        assert_eq!(
+            games[0].mainline.moves[5].comment.as_deref(),
+            Some("rest of line")
+        );
+        assert_eq!(
+            games[1].end_position().fen(),
+            "8/3k4/8/8/4P3/8/8/4K3 b - - 0 2"
+        );
+
+        let expected = r#"[Event "Test \"quoted\""]
+[Site "?"]
+[Result "1-0"]
+
+{Opening comment} 1. e4 e5 $1 {Good} (1... c5 2. Nf3 (2. c3) 2... d6) 2. Nf3 $5
+Nc6 3. Bb5 a6 {rest of line} 4. Ba4 1-0
+"#;
+
+        assert_eq!(games[0].to_string(), expected);
+
+        // Written games read back the same
+        let text = games[1].to_string();
+        let game = PgnReader::new(text.as_bytes()).next().unwrap().unwrap();
+
+        assert_eq!(game.to_string(), text);
+    }
+
+    #[test]
+    fn new_game() {
+        let mut game = Game::new(Position::from_str("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"));
+
+        game.mainline.moves.push(Node::new(Move::from_str("e2e4")));
+        game.set_result("1/2-1/2");
+
+        let text = game.to_string();
+
+        assert!(text.contains(
+            "[Result \"1/2-1/2\"]\n[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]"
+        ));
+        assert!(text.ends_with("\n\n1. e4 1/2-1/2\n"));
+    }
+
+    #[test]
+    fn errors() {
+        let pgn = "[Event \"1\"]\n\n1. e4 e5\n2. Ke3 *\n\n[Event \"2\"]\n\n1. d4 (1. e4 *\n\n[Event \"3\"]\n\n1. c4 *\n";
+        let games: Vec<_> = PgnReader::new(pgn.as_bytes()).collect();
+
+        assert_eq!(games.len(), 3);
+        assert_eq!(
+            games[0].as_ref().err(),
+            Some(&PgnError::new(4, "illegal move Ke3"))
+        );
+        assert_eq!(
+            games[1].as_ref().err(),
+            Some(&PgnError::new(8, "game result inside a variation"))
+        );
+        assert_eq!(games[2].as_ref().unwrap().tag("Event"), Some("3"));
+    }
+}
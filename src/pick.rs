@@ -3,12 +3,11 @@ use std::mem::{self, MaybeUninit};
 use crate::{
     bitboard::Bitboard,
     board::Board,
-    engine::Engine,
     gen::{Generator, MoveList},
     global::GlobalData,
     position::Position,
     r#move::Move,
-    tt::Entry,
+    tt::{Entry, TranspositionTable},
     types::{Color, ConstBlack, ConstColor, ConstWhite, Kind, Piece, Square},
 };
 
@@ -63,12 +62,12 @@ impl MoveList for PickList<'_> {
 
 impl Pick {
     pub fn new<const QUIET: bool>(
-        engine: &Engine,
+        tt: &TranspositionTable,
+        position: &Position,
         generator: &Generator,
         killer: Option<Move>,
     ) -> Self {
-        let hash = engine.position().hash();
-        let entry = engine.tt().probe(hash);
+        let entry = tt.probe(position.hash());
         let tt_move = entry.map(|entry| entry.r#move()).unwrap_or_else(Move::null);
 
         let mut pick = Pick {
@@ -86,10 +85,10 @@ impl Pick {
             tt_move,
             tt_hit: false,
             killer_hit: false,
-            position: engine.position(),
+            position,
         };
 
-        generator.generate_dyn::<QUIET>(&mut pick_list, engine.position());
+        generator.generate_dyn::<QUIET>(&mut pick_list, position);
 
         if !pick_list.tt_hit {
             pick_list.pick.entry = None;
@@ -321,8 +320,10 @@ fn sort_moves<const CAPTURE: bool>(
             0
         };
 
-        entry.score = see_score + piece_square.get(old_piece, entry.r#move.to(), phase)
-            - piece_square.get(old_piece, entry.r#move.from(), phase)
+        entry.score = see_score
+            + (piece_square.get(old_piece, entry.r#move.to())
+                - piece_square.get(old_piece, entry.r#move.from()))
+            .taper(phase)
             + new_piece.kind().value()
             - old_piece.kind().value();
     }
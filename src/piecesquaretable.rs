@@ -1,7 +1,7 @@
-use crate::types::{Color, Kind, Phase, Piece, Square};
+use crate::types::{Kind, Piece, Score, Square};
 
 pub struct PieceSquareTable {
-    values: [[[i16; 64]; Piece::COUNT]; Phase::COUNT],
+    values: [[Score; 64]; Piece::COUNT],
 }
 
 impl PieceSquareTable {
@@ -102,54 +102,43 @@ impl PieceSquareTable {
     ];
 
     pub fn new() -> Self {
-        let mut white: [[[i16; 64]; Kind::COUNT]; Phase::COUNT] =
-            [[[0; 64]; Kind::COUNT]; Phase::COUNT];
-        let mut black: [[[i16; 64]; Kind::COUNT]; Phase::COUNT] =
-            [[[0; 64]; Kind::COUNT]; Phase::COUNT];
-
-        for phase in Phase::iter() {
-            *Kind::Pawn.index_mut(phase.index_mut(&mut black)) = Self::PAWN;
-            *Kind::Knight.index_mut(phase.index_mut(&mut black)) = Self::KNIGHT;
-            *Kind::Bishop.index_mut(phase.index_mut(&mut black)) = Self::BISHOP;
-            *Kind::Rook.index_mut(phase.index_mut(&mut black)) = Self::ROOK;
-            *Kind::Queen.index_mut(phase.index_mut(&mut black)) = Self::QUEEN;
-            *Kind::King.index_mut(phase.index_mut(&mut black)) = Self::KING;
-        }
-
-        *Kind::King.index_mut(Phase::Endgame.index_mut(&mut black)) = Self::KING_END;
-        *Kind::Pawn.index_mut(Phase::Endgame.index_mut(&mut black)) = Self::PAWN_END;
-
-        // Swap black value for white
-        for phase in Phase::iter() {
-            for kind in Kind::iter() {
-                for square in Square::iter() {
-                    let value: i16 = *square.index(kind.index(phase.index(&black)));
-
-                    *square
-                        .r#for(Color::Black)
-                        .index_mut(kind.index_mut(phase.index_mut(&mut white))) = value;
-                }
+        let mut black = [[Score::ZERO; 64]; Kind::COUNT];
+
+        // Only pawns and kings play differently in the endgame
+        let tables = [
+            (Kind::Pawn, Self::PAWN, Self::PAWN_END),
+            (Kind::Knight, Self::KNIGHT, Self::KNIGHT),
+            (Kind::Bishop, Self::BISHOP, Self::BISHOP),
+            (Kind::Rook, Self::ROOK, Self::ROOK),
+            (Kind::Queen, Self::QUEEN, Self::QUEEN),
+            (Kind::King, Self::KING, Self::KING_END),
+        ];
+
+        for (kind, mg, eg) in tables {
+            for square in Square::iter() {
+                *square.index_mut(kind.index_mut(&mut black)) =
+                    Score::new(*square.index(&mg), *square.index(&eg));
             }
         }
 
         let mut table = Self {
-            values: [[[0; 64]; Piece::COUNT]; Phase::COUNT],
+            values: [[Score::ZERO; 64]; Piece::COUNT],
         };
 
-        for phase in Phase::iter() {
-            for piece in Piece::iter() {
-                *piece.index_mut(phase.index_mut(&mut table.values)) =
-                    *piece.kind().index(phase.index(&match piece.color() {
-                        Color::White => white,
-                        Color::Black => black,
-                    }));
+        // Swap black value for white
+        for piece in Piece::iter() {
+            for square in Square::iter() {
+                *square
+                    .r#for(!piece.color())
+                    .index_mut(piece.index_mut(&mut table.values)) =
+                    *square.index(piece.kind().index(&black));
             }
         }
 
         table
     }
 
-    pub fn get(&self, piece: Piece, square: Square, phase: Phase) -> i16 {
-        *square.index(piece.index(phase.index(&self.values)))
+    pub fn get(&self, piece: Piece, square: Square) -> Score {
+        *square.index(piece.index(&self.values))
     }
 }
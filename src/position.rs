@@ -7,8 +7,61 @@ use crate::{
     types::*,
 };
 
+use std::fmt;
 use std::ops::Deref;
 
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FenError {
+    FieldCount(usize),
+    RankCount(usize),
+    RankLength(Rank),
+    Piece(char),
+    Color(String),
+    CastlingRights(String),
+    EnPassant(String),
+    HalfmoveClock(String),
+    Fullmove(String),
+    KingCount(Color, usize),
+    TooManyPawns(Color),
+    TooManyPieces(Color),
+    PawnOnBackRank(Square),
+    ImpossibleCastlingRights(CastlingRights),
+    ImpossibleEnPassant(Square),
+    OpponentInCheck,
+}
+
+// Terms of king safety, attackers and their weight are only used to look up
+// the attack penalty
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct KingSafety {
+    pub shield: Score,
+    pub storm: Score,
+    pub open_files: Score,
+    pub attackers: i16,
+    pub attack_weight: i16,
+    pub attack: Score,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct PawnStructure {
+    pub protected: Score,
+    pub doubled: Score,
+    pub isolated: Score,
+    pub passed: Score,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SliderMobility {
+    pub mobility: Score,
+    pub battery: Score,
+}
+
+// Breakdown of evaluate, each term with the values of white and black
+pub struct EvalTrace {
+    pub phase: i16,
+    pub terms: Vec<(&'static str, [Score; Color::COUNT])>,
+}
+
 #[derive(Clone)]
 pub struct State {
     hash: u64,
@@ -17,6 +70,8 @@ pub struct State {
     halfmove_clock: u32,
 
     material: [i16; Color::COUNT],
+    non_pawn_material: i16,
+    piece_square: [Score; Color::COUNT],
 }
 
 pub struct UndoState {
@@ -31,6 +86,78 @@ pub struct Position {
     states: Vec<State>,
 }
 
+impl PawnStructure {
+    pub fn total(&self) -> Score {
+        self.protected + self.doubled + self.isolated + self.passed
+    }
+}
+
+impl SliderMobility {
+    pub fn total(&self) -> Score {
+        self.mobility + self.battery
+    }
+}
+
+impl EvalTrace {
+    // Sum of all terms, white minus black
+    pub fn total(&self) -> Score {
+        self.terms
+            .iter()
+            .fold(Score::ZERO, |total, (_, [white, black])| {
+                total + *white - *black
+            })
+    }
+
+    // From the view of white, as evaluate would return it with white to move
+    pub fn score(&self) -> i16 {
+        self.total().taper(self.phase)
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>15} | {:^13} | {:^13} | {:^13}",
+            "Term", "White", "Black", "Total"
+        )?;
+        writeln!(
+            f,
+            "{:>15} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}",
+            "", "MG", "EG", "MG", "EG", "MG", "EG"
+        )?;
+        writeln!(f, "{:-<16}+{:-<15}+{:-<15}+{:-<14}", "", "", "", "")?;
+
+        for (name, [white, black]) in &self.terms {
+            let total = *white - *black;
+
+            writeln!(
+                f,
+                "{name:>15} | {:>6} {:>6} | {:>6} {:>6} | {:>6} {:>6}",
+                white.mg, white.eg, black.mg, black.eg, total.mg, total.eg
+            )?;
+        }
+
+        let total = self.total();
+
+        writeln!(f, "{:-<16}+{:-<15}+{:-<15}+{:-<14}", "", "", "", "")?;
+        writeln!(
+            f,
+            "{:>15} | {:>13} | {:>13} | {:>6} {:>6}",
+            "Total", "", "", total.mg, total.eg
+        )?;
+        writeln!(f)?;
+        writeln!(f, "Phase: {} of {}", self.phase, Score::PHASE_MAX)?;
+        write!(f, "Final evaluation: {} (white side)", self.score())
+    }
+}
+
+impl KingSafety {
+    pub fn total(&self) -> Score {
+        self.shield + self.storm + self.open_files + self.attack
+    }
+}
+
 impl Position {
     pub const STARTPOS: &'static str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
@@ -39,56 +166,118 @@ impl Position {
     }
 
     pub fn from_str(fen: &str) -> Self {
-        Self::parse(&fen.split(" ").collect::<Vec<_>>())
+        Self::from_fen(fen).unwrap_or_else(|err| panic!("bad fen {fen}: {err}"))
     }
 
     pub fn parse(fen: &[&str]) -> Self {
-        let zobrist = GlobalData::get().zobrist();
+        Self::from_fields(fen).unwrap_or_else(|err| panic!("bad fen {}: {err}", fen.join(" ")))
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        Self::from_fields(&fen.split_whitespace().collect::<Vec<_>>())
+    }
+
+    // The clocks may be left out, as in EPD
+    pub fn from_fields(fen: &[&str]) -> Result<Self, FenError> {
+        let (pieces, color, castling_rights, en_passant, clocks) = match *fen {
+            [pieces, color, castling_rights, en_passant] => {
+                (pieces, color, castling_rights, en_passant, None)
+            }
+            [pieces, color, castling_rights, en_passant, halfmove_clock, fullmove] => (
+                pieces,
+                color,
+                castling_rights,
+                en_passant,
+                Some((halfmove_clock, fullmove)),
+            ),
+            _ => return Err(FenError::FieldCount(fen.len())),
+        };
+
         let mut board = Board::empty();
-        let mut hash = 0;
-        let mut material = [0, 0];
+        let ranks: Vec<_> = pieces.split("/").collect();
+
+        if ranks.len() != Rank::COUNT {
+            return Err(FenError::RankCount(ranks.len()));
+        }
 
-        for (rank, string) in Rank::iter().rev().zip(fen[0].split("/")) {
+        for (rank, string) in Rank::iter().rev().zip(ranks) {
             let mut files = File::iter();
 
             for ch in string.chars() {
-                if ch.is_ascii_digit() {
-                    for _ in '0'..ch {
-                        files.next();
+                if let Some(empty) = ch.to_digit(10).filter(|empty| (1..=8).contains(empty)) {
+                    for _ in 0..empty {
+                        files.next().ok_or(FenError::RankLength(rank))?;
                     }
                 } else {
-                    let square = Square::new(files.next().unwrap(), rank);
-                    let piece = Piece::from_char(ch);
+                    let file = files.next().ok_or(FenError::RankLength(rank))?;
+                    let piece = Piece::try_from_char(ch).ok_or(FenError::Piece(ch))?;
 
-                    board.set(square, Some(piece));
-                    hash ^= zobrist.piece(piece, square);
-                    *piece.color().index_mut(&mut material) += piece.kind().value();
+                    board.set(Square::new(file, rank), Some(piece));
                 }
             }
+
+            if files.next().is_some() {
+                return Err(FenError::RankLength(rank));
+            }
         }
 
-        let color = Color::from_str(fen[1]);
+        let color = Color::try_from_str(color).ok_or_else(|| FenError::Color(color.to_string()))?;
+
+        let castling_rights = CastlingRights::try_from_str(castling_rights)
+            .ok_or_else(|| FenError::CastlingRights(castling_rights.to_string()))?;
+
+        let en_passant = match en_passant {
+            "-" => None,
+            square => Some(
+                Square::try_from_str(square)
+                    .ok_or_else(|| FenError::EnPassant(square.to_string()))?,
+            ),
+        };
+
+        let (halfmove_clock, fullmove) = match clocks {
+            Some((halfmove_clock, fullmove)) => (
+                halfmove_clock
+                    .parse()
+                    .map_err(|_| FenError::HalfmoveClock(halfmove_clock.to_string()))?,
+                fullmove
+                    .parse::<u32>()
+                    .map_err(|_| FenError::Fullmove(fullmove.to_string()))?,
+            ),
+            None => (0, 1),
+        };
+
+        Self::validate(&board, color, castling_rights)?;
+
+        let en_passant = match en_passant {
+            Some(square) => Self::validate_en_passant(&board, color, square)?,
+            None => None,
+        };
+
+        let zobrist = GlobalData::get().zobrist();
+        let mut hash = 0;
+        let mut material = [0, 0];
+        let mut non_pawn_material = 0;
+
+        for square in board.occupied_bb() {
+            let piece = board.get(square).unwrap();
+
+            hash ^= zobrist.piece(piece, square);
+            *piece.color().index_mut(&mut material) += piece.kind().value();
+
+            if piece.kind() != Kind::Pawn && piece.kind() != Kind::King {
+                non_pawn_material += piece.kind().value();
+            }
+        }
 
         if color == Color::Black {
             hash ^= zobrist.color();
         }
 
-        let castling_rights = CastlingRights::from_str(fen[2]);
-
         hash ^= zobrist.castling_rights(castling_rights);
 
-        let en_passant = if fen[3] == "-" {
-            None
-        } else {
-            let square = Square::from_str(fen[3]);
-
+        if let Some(square) = en_passant {
             hash ^= zobrist.en_passant(square.file());
-
-            Some(square)
-        };
-
-        let halfmove_clock = fen[4].parse().unwrap();
-        let ply = fen[5].parse::<u32>().unwrap() * 2 - 2 + color as u32;
+        }
 
         let state = State {
             hash,
@@ -96,15 +285,95 @@ impl Position {
             en_passant,
             halfmove_clock,
             material,
+            non_pawn_material,
+            piece_square: Self::piece_square_from_scratch(&board),
         };
 
         let position = Self {
             board,
-            ply,
+            ply: u32::max(fullmove, 1) * 2 - 2 + color as u32,
             states: vec![state],
         };
 
-        position
+        Ok(position)
+    }
+
+    fn validate(
+        board: &Board,
+        color: Color,
+        castling_rights: CastlingRights,
+    ) -> Result<(), FenError> {
+        for side in Color::iter() {
+            let kings = board.color_kind_bb(side, Kind::King).count();
+
+            if kings != 1 {
+                return Err(FenError::KingCount(side, kings));
+            }
+
+            if board.color_kind_bb(side, Kind::Pawn).count() > 8 {
+                return Err(FenError::TooManyPawns(side));
+            }
+
+            if board.color_bb(side).count() > 16 {
+                return Err(FenError::TooManyPieces(side));
+            }
+        }
+
+        let back_ranks = Bitboard::from(Rank::_1) | Bitboard::from(Rank::_8);
+
+        if let Some(square) = (board.kind_bb(Kind::Pawn) & back_ranks).square() {
+            return Err(FenError::PawnOnBackRank(square));
+        }
+
+        for (right, side, rook) in [
+            (CastlingRights::WHITE_SHORT, Color::White, Square::H1),
+            (CastlingRights::WHITE_LONG, Color::White, Square::A1),
+            (CastlingRights::BLACK_SHORT, Color::Black, Square::H8),
+            (CastlingRights::BLACK_LONG, Color::Black, Square::A8),
+        ] {
+            let king = Square::E1.r#for(side);
+
+            if castling_rights.has(right)
+                && (board.get(king) != Some(Piece::new(side, Kind::King))
+                    || board.get(rook) != Some(Piece::new(side, Kind::Rook)))
+            {
+                return Err(FenError::ImpossibleCastlingRights(right));
+            }
+        }
+
+        let king = board.color_kind_bb(!color, Kind::King).square().unwrap();
+
+        if board.attackers(king, color) != Bitboard::EMPTY {
+            return Err(FenError::OpponentInCheck);
+        }
+
+        Ok(())
+    }
+
+    // Only keeps the square if a pawn of the side to move could capture there
+    fn validate_en_passant(
+        board: &Board,
+        color: Color,
+        square: Square,
+    ) -> Result<Option<Square>, FenError> {
+        let attack = GlobalData::get().attack();
+        let pawn = Piece::new(!color, Kind::Pawn);
+        let from = Square::new(square.file(), Rank::_2.r#for(!color));
+        let to = Square::new(square.file(), Rank::_4.r#for(!color));
+
+        if square.rank() != Rank::_6.r#for(color)
+            || board.get(square).is_some()
+            || board.get(from).is_some()
+            || board.get(to) != Some(pawn)
+        {
+            return Err(FenError::ImpossibleEnPassant(square));
+        }
+
+        if attack.pawn(square, !color) & board.color_kind_bb(color, Kind::Pawn) == Bitboard::EMPTY {
+            return Ok(None);
+        }
+
+        Ok(Some(square))
     }
 
     pub fn fen(&self) -> String {
@@ -245,8 +514,14 @@ impl Position {
     }
 
     pub fn make(&mut self, r#move: Move) -> UndoState {
+        self.make_with(r#move, |_| ())
+    }
+
+    // The hook gets the new hash as soon as it is known, e.g. to prefetch its TT entry
+    pub fn make_with(&mut self, r#move: Move, hook: impl FnOnce(u64)) -> UndoState {
         let data = GlobalData::get();
         let zobrist = data.zobrist();
+        let table = data.square();
 
         let mut state = self.state().clone();
         let piece = self.board.get(r#move.from()).unwrap();
@@ -258,11 +533,20 @@ impl Position {
         state.hash ^= zobrist.piece(piece, r#move.from());
         state.hash ^= zobrist.piece(piece, r#move.to());
 
+        *piece.color().index_mut(&mut state.piece_square) +=
+            table.get(piece, r#move.to()) - table.get(piece, r#move.from());
+
         // Capture
         if let Some(captured) = capture {
             state.hash ^= zobrist.piece(captured, r#move.to());
 
             *captured.color().index_mut(&mut state.material) -= captured.kind().value();
+            *captured.color().index_mut(&mut state.piece_square) -=
+                table.get(captured, r#move.to());
+
+            if captured.kind() != Kind::Pawn {
+                state.non_pawn_material -= captured.kind().value();
+            }
         }
 
         // Promotion
@@ -276,6 +560,9 @@ impl Position {
 
             *piece.color().index_mut(&mut state.material) -= piece.kind().value();
             *piece.color().index_mut(&mut state.material) += promotion.value();
+            *piece.color().index_mut(&mut state.piece_square) +=
+                table.get(promoted, r#move.to()) - table.get(piece, r#move.to());
+            state.non_pawn_material += promotion.value();
         }
 
         // Move rook when castling
@@ -292,6 +579,8 @@ impl Position {
 
                         state.hash ^= zobrist.piece(rook, Square::H1);
                         state.hash ^= zobrist.piece(rook, Square::F1);
+                        *rook.color().index_mut(&mut state.piece_square) +=
+                            table.get(rook, Square::F1) - table.get(rook, Square::H1);
                     }
 
                     if state.castling_rights.has(CastlingRights::WHITE_LONG)
@@ -302,6 +591,8 @@ impl Position {
 
                         state.hash ^= zobrist.piece(rook, Square::A1);
                         state.hash ^= zobrist.piece(rook, Square::D1);
+                        *rook.color().index_mut(&mut state.piece_square) +=
+                            table.get(rook, Square::D1) - table.get(rook, Square::A1);
                     }
                 }
                 Color::Black => {
@@ -313,6 +604,8 @@ impl Position {
 
                         state.hash ^= zobrist.piece(rook, Square::H8);
                         state.hash ^= zobrist.piece(rook, Square::F8);
+                        *rook.color().index_mut(&mut state.piece_square) +=
+                            table.get(rook, Square::F8) - table.get(rook, Square::H8);
                     }
 
                     if state.castling_rights.has(CastlingRights::BLACK_LONG)
@@ -323,6 +616,8 @@ impl Position {
 
                         state.hash ^= zobrist.piece(rook, Square::A8);
                         state.hash ^= zobrist.piece(rook, Square::D8);
+                        *rook.color().index_mut(&mut state.piece_square) +=
+                            table.get(rook, Square::D8) - table.get(rook, Square::A8);
                     }
                 }
             }
@@ -338,6 +633,7 @@ impl Position {
             capture = Some(Piece::new(!self.turn(), Kind::Pawn));
 
             state.hash ^= zobrist.piece(captured, taken);
+            *captured.color().index_mut(&mut state.piece_square) -= table.get(captured, taken);
         }
 
         if let Some(ep) = state.en_passant {
@@ -352,25 +648,17 @@ impl Position {
             state.halfmove_clock = 0;
         }
 
-        // Set en passant
-        if piece.kind() == Kind::Pawn {
-            match piece.color() {
-                Color::White => {
-                    if r#move.from().rank() == Rank::_2 && r#move.to().rank() == Rank::_4 {
-                        let ep = Square::new(r#move.from().file(), Rank::_3);
-
-                        state.en_passant = Some(ep);
-                        state.hash ^= zobrist.en_passant(ep.file());
-                    }
-                }
-                Color::Black => {
-                    if r#move.from().rank() == Rank::_7 && r#move.to().rank() == Rank::_5 {
-                        let ep = Square::new(r#move.from().file(), Rank::_6);
-
-                        state.en_passant = Some(ep);
-                        state.hash ^= zobrist.en_passant(ep.file());
-                    }
-                }
+        // Set en passant, only if an opponent pawn can capture
+        if piece.kind() == Kind::Pawn
+            && r#move.from().rank() == Rank::_2.r#for(piece.color())
+            && r#move.to().rank() == Rank::_4.r#for(piece.color())
+        {
+            let ep = Square::new(r#move.from().file(), Rank::_3.r#for(piece.color()));
+            let pawns = self.board.color_kind_bb(!piece.color(), Kind::Pawn);
+
+            if data.attack().pawn(ep, piece.color()) & pawns != Bitboard::EMPTY {
+                state.en_passant = Some(ep);
+                state.hash ^= zobrist.en_passant(ep.file());
             }
         }
 
@@ -384,8 +672,13 @@ impl Position {
         state.hash ^= zobrist.castling_rights(self.states.last().unwrap().castling_rights);
         state.hash ^= zobrist.castling_rights(state.castling_rights);
 
+        hook(state.hash);
+
         self.states.push(state);
 
+        #[cfg(debug_assertions)]
+        self.check_incremental();
+
         UndoState { capture, r#move }
     }
 
@@ -452,59 +745,113 @@ impl Position {
     pub fn evaluate(&self) -> i16 {
         let global = GlobalData::get();
 
-        let mut score = 0;
+        let mut score = Score::ZERO;
 
-        score += self.evaluate_piece_square_table(global) * self.turn().sign();
-        score += (self.evaluate_side::<ConstWhite>(global)
-            - self.evaluate_side::<ConstBlack>(global))
-            * self.turn().sign();
+        score += self.evaluate_piece_square_table();
+        score += self.evaluate_side::<ConstWhite>(global);
+        score -= self.evaluate_side::<ConstBlack>(global);
 
-        score
+        score.taper(self.phase()) * self.turn().sign()
     }
 
-    pub fn evaluate_side<C: ConstColor>(&self, global: &GlobalData) -> i16 {
-        let mut score: i16 = 0;
+    // Every term of evaluate per side, from the view of white
+    pub fn evaluate_trace(&self) -> EvalTrace {
+        let global = GlobalData::get();
+        let material = self.state().material.map(|material| {
+            let material = material - Kind::King.value();
+
+            Score::new(material, material)
+        });
+        let pawns = [
+            self.pawn_structure_trace::<ConstWhite>(),
+            self.pawn_structure_trace::<ConstBlack>(),
+        ];
+        let sliders = [
+            self.slider_mobility_trace::<ConstWhite>(global),
+            self.slider_mobility_trace::<ConstBlack>(global),
+        ];
+        let safety = [
+            self.king_safety_trace::<ConstWhite>(global),
+            self.king_safety_trace::<ConstBlack>(global),
+        ];
+        let rule_of_the_square = if self.is_kingpawn_endgame() {
+            [
+                self.rule_of_the_square::<ConstWhite>(),
+                self.rule_of_the_square::<ConstBlack>(),
+            ]
+        } else {
+            [Score::ZERO; Color::COUNT]
+        };
+
+        EvalTrace {
+            phase: self.phase(),
+            terms: vec![
+                ("Material", material),
+                ("Piece square", self.state().piece_square),
+                ("Protected", pawns.map(|pawns| pawns.protected)),
+                ("Doubled", pawns.map(|pawns| pawns.doubled)),
+                ("Isolated", pawns.map(|pawns| pawns.isolated)),
+                ("Passed", pawns.map(|pawns| pawns.passed)),
+                ("Mobility", sliders.map(|sliders| sliders.mobility)),
+                ("Batteries", sliders.map(|sliders| sliders.battery)),
+                (
+                    "Bishop pair",
+                    [
+                        self.bishop_pair::<ConstWhite>(),
+                        self.bishop_pair::<ConstBlack>(),
+                    ],
+                ),
+                ("Rule of square", rule_of_the_square),
+                ("King shield", safety.map(|safety| safety.shield)),
+                ("Pawn storm", safety.map(|safety| safety.storm)),
+                ("Open files", safety.map(|safety| safety.open_files)),
+                ("King attack", safety.map(|safety| safety.attack)),
+            ],
+        }
+    }
+
+    pub fn evaluate_side<C: ConstColor>(&self, global: &GlobalData) -> Score {
+        let material = *C::color().index(&self.state().material);
+        let mut score = Score::new(material, material);
 
-        score += *C::color().index(&self.state().material);
         score += self.pawn_structure::<C>();
         score += self.slider_mobility::<C>(global);
         score += self.bishop_pair::<C>();
+        score += self.king_safety::<C>(global);
 
-        match self.phase() {
-            Phase::Opening => (),
-            Phase::Middle => (),
-            Phase::Endgame => {
-                if self.is_kingpawn_endgame() {
-                    score += self.rule_of_the_square::<C>();
-                }
-            }
+        if self.is_kingpawn_endgame() {
+            score += self.rule_of_the_square::<C>();
         }
 
         score
     }
 
-    pub fn pawn_structure<C: ConstColor>(&self) -> i16 {
-        const PROTECTED: i16 = 10;
-        const DOUBLED: i16 = -20;
-        const ISOLATED: i16 = -20;
-        const PASSED: i16 = 20;
+    pub fn pawn_structure<C: ConstColor>(&self) -> Score {
+        self.pawn_structure_trace::<C>().total()
+    }
+
+    pub fn pawn_structure_trace<C: ConstColor>(&self) -> PawnStructure {
+        const PROTECTED: Score = Score::new(10, 5);
+        const DOUBLED: Score = Score::new(-10, -25);
+        const ISOLATED: Score = Score::new(-15, -20);
+        const PASSED: Score = Score::new(10, 25);
 
-        let mut score = 0;
+        let mut structure = PawnStructure::default();
 
         let pawns = self.board.color_kind_bb(C::color(), Kind::Pawn);
         let pieces = self.board.color_bb(C::color()) & !self.board.kind_bb(Kind::King);
 
         // Pieces protected by pawns
-        score += (pieces & shift::pawn_attack::<C>(pawns)).count() as i16 * PROTECTED;
+        structure.protected = PROTECTED * (pieces & shift::pawn_attack::<C>(pawns)).count() as i16;
 
         // Doubled pawns
-        score += (pawns & C::up().shift(pawns)).count() as i16 * DOUBLED;
+        structure.doubled = DOUBLED * (pawns & C::up().shift(pawns)).count() as i16;
 
         // Isolated pawns
         let squashed = shift::squash(pawns);
         let nb = Offset::<-1, 0>.shift(squashed) | Offset::<1, 0>.shift(squashed);
 
-        score += (!nb & Bitboard(0xFF)).count() as i16 * ISOLATED;
+        structure.isolated = ISOLATED * (!nb & Bitboard(0xFF)).count() as i16;
 
         let mut bb = self.board.color_kind_bb(!C::color(), Kind::Pawn);
 
@@ -516,19 +863,23 @@ impl Position {
 
         // Passed pawns
         for pawn in pawns & !bb {
-            score += pawn.rank().r#for(C::color()) as i16 * PASSED;
+            structure.passed += PASSED * pawn.rank().r#for(C::color()) as i16;
         }
 
-        score
+        structure
+    }
+
+    pub fn slider_mobility<C: ConstColor>(&self, global: &GlobalData) -> Score {
+        self.slider_mobility_trace::<C>(global).total()
     }
 
-    pub fn slider_mobility<C: ConstColor>(&self, global: &GlobalData) -> i16 {
-        const BISHOP_MOBILITY: i16 = 2;
-        const ROOK_MOBILITY: i16 = 3;
-        const BISHOP_BATTERY: i16 = 5;
-        const ROOK_BATTERY: i16 = 10;
+    pub fn slider_mobility_trace<C: ConstColor>(&self, global: &GlobalData) -> SliderMobility {
+        const BISHOP_MOBILITY: Score = Score::new(2, 3);
+        const ROOK_MOBILITY: Score = Score::new(2, 4);
+        const BISHOP_BATTERY: Score = Score::new(5, 5);
+        const ROOK_BATTERY: Score = Score::new(10, 10);
 
-        let mut score = 0;
+        let mut sliders = SliderMobility::default();
 
         let magic = global.magic();
 
@@ -539,40 +890,58 @@ impl Position {
         for square in bishops {
             let bb = magic.bishop(square, occupied);
 
-            score += bb.count() as i16 * BISHOP_MOBILITY;
-            score += (bb & bishops).count() as i16 * BISHOP_BATTERY;
+            sliders.mobility += BISHOP_MOBILITY * bb.count() as i16;
+            sliders.battery += BISHOP_BATTERY * (bb & bishops).count() as i16;
         }
 
         for square in rooks {
             let bb = magic.rook(square, occupied);
 
-            score += bb.count() as i16 * ROOK_MOBILITY;
-            score += (bb & rooks).count() as i16 * ROOK_BATTERY;
+            sliders.mobility += ROOK_MOBILITY * bb.count() as i16;
+            sliders.battery += ROOK_BATTERY * (bb & rooks).count() as i16;
         }
 
-        score
+        sliders
     }
 
-    pub fn evaluate_piece_square_table(&self, global: &GlobalData) -> i16 {
-        let phase = self.phase();
-        let table = global.square();
-        let mut score = 0;
+    pub fn evaluate_piece_square_table(&self) -> Score {
+        let [white, black] = self.state().piece_square;
 
-        for piece in Piece::iter() {
-            let bb = self.board.piece_bb(piece);
+        white - black
+    }
+
+    fn piece_square_from_scratch(board: &Board) -> [Score; Color::COUNT] {
+        let table = GlobalData::get().square();
+        let mut piece_square = [Score::ZERO; Color::COUNT];
 
-            score += bb
-                .map(|square| table.get(piece, square, phase) * piece.color().sign())
-                .sum::<i16>();
+        for piece in Piece::iter() {
+            for square in board.piece_bb(piece) {
+                *piece.color().index_mut(&mut piece_square) += table.get(piece, square);
+            }
         }
 
-        score
+        piece_square
     }
 
-    pub fn rule_of_the_square<C: ConstColor>(&self) -> i16 {
-        const RULEOFTHESQUARE: i16 = 30;
+    // Compares the incrementally updated terms with a recomputation
+    #[cfg(debug_assertions)]
+    fn check_incremental(&self) {
+        let non_pawn_material = [Kind::Knight, Kind::Bishop, Kind::Rook, Kind::Queen]
+            .into_iter()
+            .map(|kind| self.board.kind_bb(kind).count() as i16 * kind.value())
+            .sum::<i16>();
+
+        debug_assert_eq!(self.state().non_pawn_material, non_pawn_material);
+        debug_assert_eq!(
+            self.state().piece_square,
+            Self::piece_square_from_scratch(&self.board)
+        );
+    }
+
+    pub fn rule_of_the_square<C: ConstColor>(&self) -> Score {
+        const RULEOFTHESQUARE: Score = Score::new(0, 30);
 
-        let mut score = 0;
+        let mut score = Score::ZERO;
 
         let mut pawns = self.board.color_kind_bb(C::color(), Kind::Pawn);
         let mut king = self.board.color_kind_bb(!C::color(), Kind::King);
@@ -591,51 +960,130 @@ impl Position {
         score
     }
 
-    pub fn bishop_pair<C: ConstColor>(&self) -> i16 {
-        const BISHOPPAIR: i16 = 50;
+    pub fn bishop_pair<C: ConstColor>(&self) -> Score {
+        const BISHOPPAIR: Score = Score::new(40, 60);
 
         let bishops = self.board.color_kind_bb(C::color(), Kind::Bishop);
 
         if bishops.count() == 2 {
             BISHOPPAIR
         } else {
-            0
+            Score::ZERO
         }
     }
 
-    pub fn king_safety<C: ConstColor>(&self) -> i16 {
-        const SHIELD_0: i16 = 10;
-        const SHIELD_1: i16 = 20;
-        const SHIELD_2: i16 = 10;
+    pub fn king_safety<C: ConstColor>(&self, global: &GlobalData) -> Score {
+        self.king_safety_trace::<C>(global).total()
+    }
 
+    // Terms of the safety of the king of C, kept apart for the eval breakdown
+    pub fn king_safety_trace<C: ConstColor>(&self, global: &GlobalData) -> KingSafety {
+        const SHIELD_0: Score = Score::new(10, 0);
+        const SHIELD_1: Score = Score::new(20, 0);
+        const SHIELD_2: Score = Score::new(10, 0);
+        const STORM_NEAR: Score = Score::new(-20, 0);
+        const STORM_FAR: Score = Score::new(-10, 0);
+        const OPEN_FILE: Score = Score::new(-25, 0);
+        const HALF_OPEN_FILE: Score = Score::new(-10, 0);
+
+        // Per square of the king zone a piece attacks
+        const ATTACK_WEIGHT: [i16; Kind::COUNT] = [0, 2, 2, 3, 5, 0];
+
+        #[rustfmt::skip]
+        const SAFETY_TABLE: [i16; 64] = [
+              0,   0,   1,   2,   3,   5,   7,   9,
+             12,  15,  18,  22,  26,  30,  35,  39,
+             44,  50,  56,  62,  68,  75,  82,  85,
+             89,  97, 105, 113, 122, 131, 140, 150,
+            169, 180, 191, 202, 213, 225, 237, 248,
+            260, 272, 283, 295, 307, 319, 330, 342,
+            354, 366, 377, 389, 401, 412, 424, 436,
+            448, 459, 471, 483, 494, 500, 500, 500,
+        ];
+
+        let mut safety = KingSafety::default();
+        let square = self.king_square(C::color());
+        let king = Bitboard::from(square);
         let pawns = self.board.color_kind_bb(C::color(), Kind::Pawn);
+        let enemy_pawns = self.board.color_kind_bb(!C::color(), Kind::Pawn);
+
+        // Pawn shield, only for a king on the wings
         let sides = !(Bitboard::from(File::D) | Bitboard::from(File::E) | Bitboard::from(File::F));
-        let mut king = sides & self.board.color_kind_bb(C::color(), Kind::King);
+        let mut shield = sides & king;
+
+        shield |= Offset::<-1, 0>.shift(shield) | Offset::<1, 0>.shift(shield);
 
-        king |= Offset::<-1, 0>.shift(king) | Offset::<1, 0>.shift(king);
+        safety.shield = SHIELD_0 * (shield & pawns).count() as i16
+            + SHIELD_1 * (C::up().shift(shield) & pawns).count() as i16
+            + SHIELD_2 * (C::up_up().shift(shield) & pawns).count() as i16;
 
-        (king & pawns).count() as i16 * SHIELD_0
-            + (C::up().shift(king) & pawns).count() as i16 * SHIELD_1
-            + (C::up_up().shift(king) & pawns).count() as i16 * SHIELD_2
+        // Enemy pawns advancing on the king and its neighbouring files
+        let front = king | Offset::<-1, 0>.shift(king) | Offset::<1, 0>.shift(king);
+        let near = C::up().shift(front) | C::up_up().shift(front);
+        let far = C::up().shift(C::up_up().shift(front));
+
+        safety.storm = STORM_NEAR * (near & enemy_pawns).count() as i16
+            + STORM_FAR * (far & enemy_pawns).count() as i16;
+
+        // Files without own pawns next to the king
+        for file in File::iter().filter(|file| (square.file() as i8 - *file as i8).abs() <= 1) {
+            let file = Bitboard::from(file);
+
+            if file & pawns == Bitboard::EMPTY {
+                safety.open_files += if file & enemy_pawns == Bitboard::EMPTY {
+                    OPEN_FILE
+                } else {
+                    HALF_OPEN_FILE
+                };
+            }
+        }
+
+        // Pieces attacking the squares around the king
+        let attack = global.attack();
+        let magic = global.magic();
+        let occupied = self.occupied_bb();
+        let zone = attack.king(square) | king | C::up().shift(attack.king(square));
+
+        for kind in [Kind::Knight, Kind::Bishop, Kind::Rook, Kind::Queen] {
+            for from in self.board.color_kind_bb(!C::color(), kind) {
+                let attacks = match kind {
+                    Kind::Knight => attack.knight(from),
+                    Kind::Bishop => magic.bishop(from, occupied),
+                    Kind::Rook => magic.rook(from, occupied),
+                    _ => magic.bishop(from, occupied) | magic.rook(from, occupied),
+                };
+                let count = (attacks & zone).count() as i16;
+
+                if count > 0 {
+                    safety.attackers += 1;
+                    safety.attack_weight += count * *kind.index(&ATTACK_WEIGHT);
+                }
+            }
+        }
+
+        // A lone attacker is rarely dangerous
+        if safety.attackers >= 2 {
+            let penalty = SAFETY_TABLE[(safety.attack_weight as usize).min(SAFETY_TABLE.len() - 1)];
+
+            safety.attack = Score::new(-penalty, -penalty / 4);
+        }
+
+        safety
     }
 
     pub fn is_kingpawn_endgame(&self) -> bool {
         self.board.kind_bb(Kind::King) | self.board.kind_bb(Kind::Pawn) == self.board.occupied_bb()
     }
 
-    pub fn all_material(&self) -> i16 {
-        self.state().material.iter().sum::<i16>() - 2 * Kind::King.value()
-    }
+    // Blends from Score::PHASE_MAX with most pieces on the board down to 0
+    // once little more than a rook and a minor piece are left
+    pub fn phase(&self) -> i16 {
+        const MIDGAME: i32 = 5800;
+        const ENDGAME: i32 = 1300;
 
-    pub fn phase(&self) -> Phase {
-        const MIDGAME: i16 = 3700;
-        const ENDGAME: i16 = 1700;
+        let material = (self.state().non_pawn_material as i32).clamp(ENDGAME, MIDGAME);
 
-        match self.all_material() {
-            MIDGAME.. => Phase::Opening,
-            ENDGAME..MIDGAME => Phase::Middle,
-            ..ENDGAME => Phase::Endgame,
-        }
+        ((material - ENDGAME) * Score::PHASE_MAX as i32 / (MIDGAME - ENDGAME)) as i16
     }
 }
 
@@ -647,6 +1095,33 @@ impl Deref for Position {
     }
 }
 
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FieldCount(count) => write!(f, "expected 4 or 6 fields, got {count}"),
+            Self::RankCount(count) => write!(f, "expected 8 ranks, got {count}"),
+            Self::RankLength(rank) => write!(f, "rank {rank} does not have 8 squares"),
+            Self::Piece(ch) => write!(f, "bad piece {ch}"),
+            Self::Color(s) => write!(f, "bad side to move {s}"),
+            Self::CastlingRights(s) => write!(f, "bad castling rights {s}"),
+            Self::EnPassant(s) => write!(f, "bad en passant square {s}"),
+            Self::HalfmoveClock(s) => write!(f, "bad halfmove clock {s}"),
+            Self::Fullmove(s) => write!(f, "bad fullmove number {s}"),
+            Self::KingCount(color, count) => write!(f, "{color} has {count} kings"),
+            Self::TooManyPawns(color) => write!(f, "{color} has more than 8 pawns"),
+            Self::TooManyPieces(color) => write!(f, "{color} has more than 16 pieces"),
+            Self::PawnOnBackRank(square) => write!(f, "pawn on back rank at {square}"),
+            Self::ImpossibleCastlingRights(right) => {
+                write!(f, "castling right {right} without king and rook in place")
+            }
+            Self::ImpossibleEnPassant(square) => {
+                write!(f, "en passant square {square} without a double pawn push")
+            }
+            Self::OpponentInCheck => write!(f, "side not to move is in check"),
+        }
+    }
+}
+
 fn castling_rights_mask(square: Square) -> CastlingRights {
     match square {
         Square::H1 => !CastlingRights::WHITE_SHORT,
@@ -714,6 +1189,164 @@ mod tests {
             let cpy = Position::parse(&fen);
 
             assert!(position.hash() == cpy.hash());
+            assert!(position.phase() == cpy.phase());
         }
     }
+
+    #[test]
+    fn king_safety() {
+        let global = GlobalData::get();
+        let quiet = Position::new();
+        let attacked =
+            Position::from_str("r1bq1rk1/pppp1ppp/2n5/2b1p1NQ/2B1P3/8/PPPP1PPP/RNB1K2R b KQ - 0 1");
+
+        assert_eq!(
+            quiet.king_safety_trace::<ConstWhite>(global),
+            quiet.king_safety_trace::<ConstBlack>(global)
+        );
+        assert_eq!(
+            quiet.king_safety_trace::<ConstWhite>(global).attack,
+            Score::ZERO
+        );
+
+        // Queen, knight and bishop all hit the squares around the castled king
+        let safety = attacked.king_safety_trace::<ConstBlack>(global);
+
+        assert_eq!(safety.attackers, 3);
+        assert!(safety.attack.mg < -100, "{safety:?}");
+        assert!(safety.total().mg < attacked.king_safety::<ConstWhite>(global).mg);
+
+        // Open and half open files next to the king
+        let open = Position::from_str("6k1/5p1p/8/8/8/8/5P1P/6K1 w - - 0 1");
+        let half_open = Position::from_str("6k1/5p1p/8/8/6P1/8/5P1P/6K1 w - - 0 1");
+
+        assert_eq!(
+            open.king_safety_trace::<ConstWhite>(global).open_files,
+            Score::new(-25, 0)
+        );
+        assert_eq!(
+            half_open.king_safety_trace::<ConstWhite>(global).open_files,
+            Score::ZERO
+        );
+        assert_eq!(
+            half_open.king_safety_trace::<ConstBlack>(global).open_files,
+            Score::new(-10, 0)
+        );
+    }
+
+    #[test]
+    fn evaluate_trace() {
+        for fen in FENS {
+            let position = Position::from_str(fen);
+            let trace = position.evaluate_trace();
+
+            assert_eq!(
+                trace.score() * position.turn().sign(),
+                position.evaluate(),
+                "{fen}"
+            );
+        }
+
+        // Mirrored positions have mirrored traces
+        let white = Position::from_str("4k3/8/8/8/8/8/PP2P3/4K3 w - - 0 1").evaluate_trace();
+        let black = Position::from_str("4k3/pp2p3/8/8/8/8/8/4K3 b - - 0 1").evaluate_trace();
+
+        for ((name, [w, b]), (_, [mirror_w, mirror_b])) in white.terms.iter().zip(&black.terms) {
+            assert_eq!((w, b), (mirror_b, mirror_w), "{name}");
+        }
+
+        assert!(white.to_string().contains("Final evaluation"));
+    }
+
+    #[test]
+    fn phase() {
+        let mut position = Position::from_str("3qk3/8/8/8/8/8/3Q4/3QK3 b - - 0 1");
+        let phase = position.phase();
+
+        assert_eq!(Position::new().phase(), Score::PHASE_MAX);
+        assert_eq!(
+            Position::from_str("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").phase(),
+            0
+        );
+        assert!(0 < phase && phase < Score::PHASE_MAX);
+
+        // Trading pieces moves the phase towards the endgame a step at a time
+        let undo = position.make(Move::from_str("d8d2"));
+
+        assert!(position.phase() < phase);
+        assert!(position.phase() > 0);
+
+        position.unmake(undo);
+
+        assert_eq!(position.phase(), phase);
+    }
+
+    #[test]
+    fn fen_errors() {
+        let cases = [
+            ("8/8/8/8/8/8/8 w - - 0 1", FenError::RankCount(7)),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN w - -",
+                FenError::RankLength(Rank::_1),
+            ),
+            ("4k3/8/8/8/8/8/8/4KX2 w - -", FenError::Piece('X')),
+            (
+                "4k3/8/8/8/8/8/8/4K3 x - -",
+                FenError::Color("x".to_string()),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/8 w - -",
+                FenError::KingCount(Color::White, 0),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/3KK3 w - -",
+                FenError::KingCount(Color::White, 2),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/P3K3 w - -",
+                FenError::PawnOnBackRank(Square::A1),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w K -",
+                FenError::ImpossibleCastlingRights(CastlingRights::WHITE_SHORT),
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - e3",
+                FenError::ImpossibleEnPassant(Square::E3),
+            ),
+            ("4k2R/8/8/8/8/8/8/4K3 w - -", FenError::OpponentInCheck),
+            (
+                "4k3/8/8/8/8/8/8/4K3 w - - x 1",
+                FenError::HalfmoveClock("x".to_string()),
+            ),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0", FenError::FieldCount(5)),
+        ];
+
+        for (fen, err) in cases {
+            assert_eq!(Position::from_fen(fen).err(), Some(err), "{fen}");
+        }
+    }
+
+    #[test]
+    fn fen_en_passant() {
+        // Four field FENs default the clocks
+        let position = Position::from_fen("4k3/8/8/8/4Pp2/8/8/4K3 b - e3").unwrap();
+
+        assert!(position.en_passant() == Some(Square::E3));
+        assert!(position.fen() == "4k3/8/8/8/4Pp2/8/8/4K3 b - e3 0 1");
+
+        // En passant squares nobody can capture on are dropped
+        let position = Position::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap();
+
+        assert!(position.en_passant().is_none());
+
+        let mut position = Position::new();
+
+        position.make(Move::from_str("e2e4"));
+
+        let cpy = Position::from_fen(&position.fen()).unwrap();
+
+        assert!(position.en_passant().is_none());
+        assert!(position.hash() == cpy.hash());
+    }
 }
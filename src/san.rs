@@ -0,0 +1,285 @@
+use std::fmt;
+
+use crate::{
+    bitboard::Bitboard,
+    gen::{self, Generator, MoveVec},
+    position::Position,
+    r#move::Move,
+    types::{File, Kind, Rank, Square},
+};
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SanError {
+    Syntax(String),
+    Illegal(String),
+    Ambiguous(String),
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(san) => write!(f, "bad move {san}"),
+            Self::Illegal(san) => write!(f, "illegal move {san}"),
+            Self::Ambiguous(san) => write!(f, "ambiguous move {san}"),
+        }
+    }
+}
+
+impl Position {
+    // The move must be legal in this position
+    pub fn to_san(&self, r#move: Move) -> String {
+        let piece = self.get(r#move.from()).unwrap();
+        let mut san = String::new();
+
+        if piece.kind() == Kind::King
+            && (r#move.from().file() as i8 - r#move.to().file() as i8).abs() == 2
+        {
+            san.push_str(match r#move.to().file() {
+                File::G => "O-O",
+                _ => "O-O-O",
+            });
+        } else {
+            let capture = self.captured_piece(r#move).is_some();
+
+            if piece.kind() == Kind::Pawn {
+                if capture {
+                    san.push(r#move.from().file().to_char());
+                }
+            } else {
+                san.push(piece.kind().to_char().to_ascii_uppercase());
+                san.push_str(&self.disambiguation(r#move));
+            }
+
+            if capture {
+                san.push('x');
+            }
+
+            san.push_str(&r#move.to().to_string());
+
+            if let Some(kind) = r#move.kind() {
+                san.push('=');
+                san.push(kind.to_char().to_ascii_uppercase());
+            }
+        }
+
+        let mut position = self.clone();
+
+        position.make(r#move);
+
+        if Generator::new_dyn(&position).checkers() != Bitboard::EMPTY {
+            let mut count = 0;
+
+            gen::generate_dyn::<true>(&mut count, &position);
+
+            san.push(if count == 0 { '#' } else { '+' });
+        }
+
+        san
+    }
+
+    // Only as much of the origin as needed to tell the move apart
+    fn disambiguation(&self, r#move: Move) -> String {
+        let piece = self.get(r#move.from()).unwrap();
+        let mut moves = MoveVec::new();
+
+        gen::generate_dyn::<true>(&mut moves, self);
+
+        let others: Vec<_> = moves
+            .moves()
+            .iter()
+            .filter(|m| m.to() == r#move.to() && m.from() != r#move.from())
+            .filter(|m| self.get(m.from()) == Some(piece))
+            .map(|m| m.from())
+            .collect();
+
+        let from = r#move.from();
+
+        if others.is_empty() {
+            String::new()
+        } else if others.iter().all(|square| square.file() != from.file()) {
+            from.file().to_string()
+        } else if others.iter().all(|square| square.rank() != from.rank()) {
+            from.rank().to_string()
+        } else {
+            from.to_string()
+        }
+    }
+
+    // Accepts 0-0 for O-O, promotions with or without =, and missing or extra suffixes
+    pub fn parse_san(&self, san: &str) -> Result<Move, SanError> {
+        let syntax = || SanError::Syntax(san.to_string());
+        let text = san.trim_end_matches(['+', '#', '!', '?']);
+
+        if !text.is_ascii() || text.is_empty() {
+            return Err(syntax());
+        }
+
+        let mut moves = MoveVec::new();
+
+        gen::generate_dyn::<true>(&mut moves, self);
+
+        let candidates: Vec<Move> = match text {
+            "O-O" | "0-0" | "O-O-O" | "0-0-0" => {
+                let king = self.king_square(self.turn());
+                let file = if text.len() == 3 { File::G } else { File::C };
+
+                // Castling always starts from the e-file
+                if king.file() != File::E {
+                    return Err(SanError::Illegal(san.to_string()));
+                }
+
+                moves
+                    .moves()
+                    .iter()
+                    .copied()
+                    .filter(|m| m.from() == king && m.to() == Square::new(file, king.rank()))
+                    .collect()
+            }
+            _ => {
+                let mut text = text;
+
+                // Promotion piece, optionally preceded by =
+                let promotion = match text.as_bytes() {
+                    [.., ch] if ch.is_ascii_alphabetic() => {
+                        text = text[..text.len() - 1].trim_end_matches('=');
+
+                        match Kind::try_from_char(ch.to_ascii_lowercase() as char) {
+                            Some(
+                                kind @ (Kind::Knight | Kind::Bishop | Kind::Rook | Kind::Queen),
+                            ) => Some(kind),
+                            _ => return Err(syntax()),
+                        }
+                    }
+                    _ => None,
+                };
+
+                let split = text.len().checked_sub(2).ok_or_else(syntax)?;
+                let to = Square::try_from_str(&text[split..]).ok_or_else(syntax)?;
+                let mut prefix = &text[..split];
+
+                let kind = match prefix.as_bytes() {
+                    [ch, ..] if ch.is_ascii_uppercase() => {
+                        prefix = &prefix[1..];
+
+                        Kind::try_from_char(ch.to_ascii_lowercase() as char).ok_or_else(syntax)?
+                    }
+                    _ => Kind::Pawn,
+                };
+
+                let prefix = prefix.trim_end_matches(['x', ':', '-']);
+                let mut file = None;
+                let mut rank = None;
+
+                for ch in prefix.chars() {
+                    if let (None, None, Some(f)) = (file, rank, File::try_from_char(ch)) {
+                        file = Some(f);
+                    } else if let (None, Some(r)) = (rank, Rank::try_from_char(ch)) {
+                        rank = Some(r);
+                    } else {
+                        return Err(syntax());
+                    }
+                }
+
+                moves
+                    .moves()
+                    .iter()
+                    .copied()
+                    .filter(|m| m.to() == to && m.kind() == promotion)
+                    .filter(|m| self.get(m.from()).map(|piece| piece.kind()) == Some(kind))
+                    .filter(|m| file.is_none_or(|file| m.from().file() == file))
+                    .filter(|m| rank.is_none_or(|rank| m.from().rank() == rank))
+                    .collect()
+            }
+        };
+
+        match candidates[..] {
+            [r#move] => Ok(r#move),
+            [] => Err(SanError::Illegal(san.to_string())),
+            _ => Err(SanError::Ambiguous(san.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_san() {
+        let cases = [
+            (Position::STARTPOS, "g1f3", "Nf3"),
+            (Position::STARTPOS, "e2e4", "e4"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1", "O-O"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8", "O-O-O"),
+            ("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "a1d1", "Rad1"),
+            ("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "h1f1", "Rhf1"),
+            ("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3", "R1a3"),
+            ("2k5/8/8/8/4Q2Q/8/K7/7Q w - - 0 1", "h4e1", "Qh4e1"),
+            ("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q", "b8=Q+"),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", "exd6"),
+            ("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Ra8#"),
+        ];
+
+        for (fen, r#move, san) in cases {
+            let position = Position::from_str(fen);
+
+            assert_eq!(position.to_san(Move::from_str(r#move)), san, "{fen}");
+        }
+    }
+
+    #[test]
+    fn parse_san() {
+        let cases = [
+            (Position::STARTPOS, "Nf3", Ok("g1f3")),
+            (Position::STARTPOS, "e4!?", Ok("e2e4")),
+            (Position::STARTPOS, "Ng1f3", Ok("g1f3")),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "0-0", Ok("e1g1")),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "O-O-O", Ok("e8c8")),
+            ("4k3/8/8/8/8/8/4K3/R6R w - - 0 1", "Rad1", Ok("a1d1")),
+            ("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8Q", Ok("b7b8q")),
+            ("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8=N", Ok("b7b8n")),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "exd6", Ok("e5d6")),
+            ("6k1/5ppp/8/8/8/8/8/R3K3 w - - 0 1", "Ra8", Ok("a1a8")),
+            (
+                "4k3/8/8/8/8/8/4K3/R6R w - - 0 1",
+                "Rd1",
+                Err(SanError::Ambiguous("Rd1".to_string())),
+            ),
+            (
+                Position::STARTPOS,
+                "Nf4",
+                Err(SanError::Illegal("Nf4".to_string())),
+            ),
+            (
+                Position::STARTPOS,
+                "O-O",
+                Err(SanError::Illegal("O-O".to_string())),
+            ),
+            (
+                "4k3/1P6/8/8/8/8/8/4K3 w - - 0 1",
+                "b8",
+                Err(SanError::Illegal("b8".to_string())),
+            ),
+            (
+                Position::STARTPOS,
+                "Zf3",
+                Err(SanError::Syntax("Zf3".to_string())),
+            ),
+            (
+                Position::STARTPOS,
+                "",
+                Err(SanError::Syntax("".to_string())),
+            ),
+        ];
+
+        for (fen, san, r#move) in cases {
+            let position = Position::from_str(fen);
+
+            assert_eq!(
+                position.parse_san(san),
+                r#move.map(Move::from_str),
+                "{fen} {san}"
+            );
+        }
+    }
+}
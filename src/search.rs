@@ -1,56 +1,178 @@
-use std::{collections::HashSet, time::Instant};
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
     bitboard::Bitboard,
     engine::Engine,
     gen::{self, Generator, MoveVec},
     pick::Pick,
+    position::Position,
     r#move::Move,
     searchlimits::SearchLimits,
     tt::{Bound, Entry},
+    types::Color,
 };
 
 const MAX_SCORE: i16 = i16::MAX / 2;
 const MIN_SCORE: i16 = -MAX_SCORE;
 const MATE_SCORE: i16 = MAX_SCORE / 2;
 
-struct Stats {
+pub struct SearchResult {
+    pub pv: Vec<Move>,
+    // None when the move came from the book
+    pub score: Option<i16>,
+    pub nodes: usize,
+}
+
+// State shared by all threads of one search
+struct Shared {
+    nodes: Vec<AtomicUsize>,
+    done: AtomicBool,
+}
+
+struct Stats<'a> {
+    id: usize,
+    shared: &'a Shared,
     best_index_distribution: Vec<usize>,
     killer_moves: Vec<(Move, i16)>,
     root_ply: u32,
+    color: Color,
+    limits: &'a SearchLimits,
+    start: Instant,
+    end: Option<Instant>,
+    ponder: bool,
+    nodes: usize,
+    seldepth: usize,
+    root_moves: Vec<Move>,
+    root_best: Move,
+    pv_index: usize,
 }
 
-fn quiesce(engine: &mut Engine, stats: &mut Stats, mut alpha: i16, beta: i16) -> i16 {
+impl<'a> Stats<'a> {
+    fn new(
+        engine: &Engine,
+        shared: &'a Shared,
+        id: usize,
+        limits: &'a SearchLimits,
+        root_moves: Vec<Move>,
+    ) -> Self {
+        let color = engine.position().turn();
+
+        Self {
+            id,
+            shared,
+            best_index_distribution: Vec::new(),
+            killer_moves: Vec::new(),
+            root_ply: engine.position().ply(),
+            color,
+            limits,
+            start: Instant::now(),
+            end: limits.get_end_time(color, engine.options.move_overhead),
+            ponder: limits.ponder(),
+            nodes: 0,
+            seldepth: 0,
+            root_moves,
+            root_best: Move::null(),
+            pv_index: 0,
+        }
+    }
+
+    fn add_node(&mut self) {
+        self.nodes += 1;
+        self.shared.nodes[self.id].store(self.nodes, Ordering::Relaxed);
+    }
+
+    // Nodes of all threads
+    fn total_nodes(&self) -> usize {
+        self.shared
+            .nodes
+            .iter()
+            .map(|nodes| nodes.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    // Cheap enough to check at every node, unlike the clock
+    fn is_out_of_nodes(&self) -> bool {
+        self.id == 0
+            && self.limits.nodes() != usize::MAX
+            && self.total_nodes() >= self.limits.nodes()
+    }
+
+    fn is_over(&mut self, engine: &Engine) -> bool {
+        if engine.is_stopped() || self.shared.done.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        // Helpers run until the main thread is done
+        if self.id != 0 {
+            return false;
+        }
+
+        // Start the clock on ponderhit, keeping the tree searched so far
+        if self.ponder {
+            if engine.is_pondering() {
+                return false;
+            }
+
+            self.ponder = false;
+            self.end = self
+                .limits
+                .get_end_time(self.color, engine.options.move_overhead);
+        }
+
+        self.end.is_some_and(|end| Instant::now() >= end)
+    }
+}
+
+fn quiesce(
+    engine: &Engine,
+    position: &mut Position,
+    stats: &mut Stats<'_>,
+    mut alpha: i16,
+    beta: i16,
+) -> Option<i16> {
+    if stats.is_out_of_nodes() {
+        return None;
+    }
+
+    stats.add_node();
+
     let mut best_move = Move::null();
     let mut best_index = None;
     let mut bound = Bound::Upper;
-    let generator = Generator::new_dyn(engine.position());
-    let ply_index = (engine.position().ply() - stats.root_ply) as usize;
+    let generator = Generator::new_dyn(position);
+    let ply_index = (position.ply() - stats.root_ply) as usize;
     let killer = stats.killer_moves.get(ply_index).map(|(r#move, _)| *r#move);
     let in_check = generator.checkers() != Bitboard(0);
 
+    stats.seldepth = usize::max(stats.seldepth, ply_index);
+
     let mut pick = if in_check {
-        Pick::new::<true>(engine, &generator, killer)
+        Pick::new::<true>(engine.tt(), position, &generator, killer)
     } else {
-        Pick::new::<false>(engine, &generator, killer)
+        Pick::new::<false>(engine.tt(), position, &generator, killer)
     };
 
     if let Some(entry) = pick.entry() {
         // TT cut
         if match entry.bound() {
             Bound::Exact => true,
-            Bound::Lower => entry.score() >= beta,
-            Bound::Upper => entry.score() < alpha,
+            Bound::Lower => score_from_tt(entry.score(), ply_index) >= beta,
+            Bound::Upper => score_from_tt(entry.score(), ply_index) < alpha,
         } {
-            return entry.score();
+            return Some(score_from_tt(entry.score(), ply_index));
         }
     };
 
-    let mut best_score = engine.position().evaluate();
+    let mut best_score = position.evaluate();
 
     if in_check {
         if pick.is_empty() {
-            return MIN_SCORE + engine.position().ply() as i16 + 1;
+            return Some(MIN_SCORE + ply_index as i16 + 1);
         } else {
             best_score = MIN_SCORE;
         }
@@ -60,16 +182,18 @@ fn quiesce(engine: &mut Engine, stats: &mut Stats, mut alpha: i16, beta: i16) ->
         }
 
         if best_score >= beta {
-            return best_score;
+            return Some(best_score);
         }
     }
 
     // Search all children
-    while let Some((i, r#move)) = pick.next(engine.position()) {
-        let undo = engine.position_mut().make(r#move);
-        let score = -quiesce(engine, stats, -beta, -alpha);
+    while let Some((i, r#move)) = pick.next(position) {
+        let undo = position.make_with(r#move, |hash| engine.tt().prefetch(hash));
+        let score = quiesce(engine, position, stats, -beta, -alpha);
 
-        engine.position_mut().unmake(undo);
+        position.unmake(undo);
+
+        let score = -score?;
 
         if score > best_score {
             best_score = score;
@@ -90,12 +214,14 @@ fn quiesce(engine: &mut Engine, stats: &mut Stats, mut alpha: i16, beta: i16) ->
     }
 
     // Update TT
-    let hash = engine.position().hash();
+    let hash = position.hash();
     let age = engine.age();
 
+    let score = score_to_tt(best_score, ply_index);
+
     engine
-        .tt_mut()
-        .insert(Entry::new(hash, age, best_move, 0, best_score, bound));
+        .tt()
+        .insert(Entry::new(hash, age, best_move, 0, score, bound));
 
     // Update stats
     if let Some(best_index) = best_index {
@@ -115,26 +241,28 @@ fn quiesce(engine: &mut Engine, stats: &mut Stats, mut alpha: i16, beta: i16) ->
         stats.killer_moves[ply_index] = (best_move, best_score);
     }
 
-    return best_score;
+    Some(best_score)
 }
 
 fn alpha_beta(
-    engine: &mut Engine,
-    stats: &mut Stats,
-    end: Instant,
+    engine: &Engine,
+    position: &mut Position,
+    stats: &mut Stats<'_>,
     mut alpha: i16,
     beta: i16,
     depth: u16,
     root: bool,
 ) -> Option<i16> {
-    if depth == 0 {
-        return Some(quiesce(engine, stats, alpha, beta));
-        // return Some(engine.position().evaluate());
-    } else if depth >= 4 && Instant::now() >= end {
+    if stats.is_out_of_nodes() || depth >= 4 && stats.is_over(engine) {
         return None;
+    } else if depth == 0 {
+        return quiesce(engine, position, stats, alpha, beta);
+        // return Some(engine.position().evaluate());
     }
 
-    if !root && engine.position().is_technical_draw() {
+    stats.add_node();
+
+    if !root && position.is_technical_draw() {
         return Some(0);
     }
 
@@ -142,11 +270,14 @@ fn alpha_beta(
     let mut best_move = Move::null();
     let mut best_index = None;
     let mut bound = Bound::Upper;
-    let generator = Generator::new_dyn(engine.position());
-    let ply_index = (engine.position().ply() - stats.root_ply) as usize;
+    let generator = Generator::new_dyn(position);
+    let ply_index = (position.ply() - stats.root_ply) as usize;
+
+    stats.seldepth = usize::max(stats.seldepth, ply_index);
 
     let mut pick = Pick::new::<true>(
-        engine,
+        engine.tt(),
+        position,
         &generator,
         stats.killer_moves.get(ply_index).map(|(r#move, _)| *r#move),
     );
@@ -154,34 +285,43 @@ fn alpha_beta(
     // Checkmate or draw
     if pick.is_empty() {
         if generator.checkers() != Bitboard(0) {
-            return Some(MIN_SCORE + engine.position().ply() as i16 + 1);
+            return Some(MIN_SCORE + ply_index as i16 + 1);
         } else {
             return Some(0);
         }
     }
 
     // TT cut
-    if let Some(entry) = pick.entry() {
+    if let Some(entry) = pick.entry().filter(|_| !root) {
         if match entry.bound() {
             Bound::Exact => true,
-            Bound::Lower => entry.score() >= beta,
-            Bound::Upper => entry.score() < alpha,
+            Bound::Lower => score_from_tt(entry.score(), ply_index) >= beta,
+            Bound::Upper => score_from_tt(entry.score(), ply_index) < alpha,
         } && entry.depth() >= depth
         {
-            return Some(entry.score());
+            return Some(score_from_tt(entry.score(), ply_index));
         }
     }
 
     // Null move pruning
-    if generator.checkers() == Bitboard(0)
-        && !engine.position().is_king_and_pawn(engine.position().turn())
+    if !root
+        && generator.checkers() == Bitboard(0)
+        && !position.is_king_and_pawn(position.turn())
         && depth >= 3
     {
-        engine.position_mut().make_null();
-
-        let score = alpha_beta(engine, stats, end, -beta, -(beta - 1), depth - 3, false);
+        position.make_null();
+
+        let score = alpha_beta(
+            engine,
+            position,
+            stats,
+            -beta,
+            -(beta - 1),
+            depth - 3,
+            false,
+        );
 
-        engine.position_mut().unmake_null();
+        position.unmake_null();
 
         let score = -score?;
 
@@ -190,16 +330,36 @@ fn alpha_beta(
         }
     }
 
+    let mut move_number = 0;
+
     // Search all children
-    while let Some((i, r#move)) = pick.next(engine.position()) {
-        let undo = engine.position_mut().make(r#move);
+    while let Some((i, r#move)) = pick.next(position) {
+        if root {
+            if !stats.root_moves.contains(&r#move) {
+                continue;
+            }
+
+            move_number += 1;
+
+            if stats.id == 0 && stats.start.elapsed() >= Duration::from_secs(3) {
+                println!("info depth {depth} currmove {move} currmovenumber {move_number}");
+            }
+        }
+
+        let undo = position.make_with(r#move, |hash| engine.tt().prefetch(hash));
 
         let score = if i >= 2 && depth >= 2 {
-            if let Some(score) =
-                alpha_beta(engine, stats, end, -(alpha + 1), -alpha, depth - 2, false)
-            {
+            if let Some(score) = alpha_beta(
+                engine,
+                position,
+                stats,
+                -(alpha + 1),
+                -alpha,
+                depth - 2,
+                false,
+            ) {
                 if -score > alpha {
-                    alpha_beta(engine, stats, end, -beta, -alpha, depth - 1, false)
+                    alpha_beta(engine, position, stats, -beta, -alpha, depth - 1, false)
                 } else {
                     Some(score)
                 }
@@ -207,10 +367,10 @@ fn alpha_beta(
                 None
             }
         } else {
-            alpha_beta(engine, stats, end, -beta, -alpha, depth - 1, false)
+            alpha_beta(engine, position, stats, -beta, -alpha, depth - 1, false)
         };
 
-        engine.position_mut().unmake(undo);
+        position.unmake(undo);
 
         let score = -score?;
 
@@ -232,13 +392,20 @@ fn alpha_beta(
         }
     }
 
-    // Update TT
-    let hash = engine.position().hash();
-    let age = engine.age();
+    if root {
+        stats.root_best = best_move;
+    }
 
-    engine
-        .tt_mut()
-        .insert(Entry::new(hash, age, best_move, depth, best_score, bound));
+    // Update TT, keeping the root entry for the best line
+    if !root || stats.pv_index == 0 {
+        let hash = position.hash();
+        let age = engine.age();
+        let score = score_to_tt(best_score, ply_index);
+
+        engine
+            .tt()
+            .insert(Entry::new(hash, age, best_move, depth, score, bound));
+    }
 
     // Update stats
     if let Some(best_index) = best_index {
@@ -261,10 +428,92 @@ fn alpha_beta(
     Some(best_score)
 }
 
-fn get_pv(engine: &mut Engine, pv: &mut Vec<Move>, visited: &mut HashSet<u64>) {
-    let hash = engine.position().hash();
+fn print_info(
+    engine: &Engine,
+    stats: &Stats<'_>,
+    depth: u16,
+    pv_index: usize,
+    score: i16,
+    bound: &str,
+    pv: &[Move],
+) {
+    let elapsed = stats.start.elapsed();
+    let total = stats.total_nodes();
+    let nps = (total as f64 / elapsed.as_secs_f64().max(0.001)) as usize;
+    let hashfull = engine.tt().hashfull(engine.age());
+
+    print!(
+        "info depth {depth} seldepth {} multipv {} score ",
+        stats.seldepth,
+        pv_index + 1
+    );
+
+    if let Some(mate) = mate_distance(score) {
+        print!("mate {mate}");
+    } else {
+        print!("cp {score}");
+    }
+
+    if !bound.is_empty() {
+        print!(" {bound}");
+    }
+
+    print!(
+        " nodes {} nps {nps} hashfull {hashfull} tbhits 0 time {} pv",
+        total,
+        elapsed.as_millis()
+    );
+
+    for r#move in pv {
+        print!(" {}", r#move);
+    }
+
+    println!();
+}
+
+// Moves until mate, negative if we are getting mated
+fn mate_distance(score: i16) -> Option<i16> {
+    if score > MATE_SCORE {
+        Some((MAX_SCORE - score) / 2)
+    } else if score < -MATE_SCORE {
+        Some((MIN_SCORE - score + 1) / 2)
+    } else {
+        None
+    }
+}
+
+// Mate scores count plies from the root, but the TT stores them counted from
+// the node itself so they stay valid when reached through another path
+fn score_to_tt(score: i16, ply_index: usize) -> i16 {
+    if score > MATE_SCORE {
+        score + ply_index as i16
+    } else if score < -MATE_SCORE {
+        score - ply_index as i16
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i16, ply_index: usize) -> i16 {
+    if score > MATE_SCORE {
+        score - ply_index as i16
+    } else if score < -MATE_SCORE {
+        score + ply_index as i16
+    } else {
+        score
+    }
+}
+
+fn get_pv(
+    engine: &Engine,
+    position: &mut Position,
+    pv: &mut Vec<Move>,
+    visited: &mut HashSet<u64>,
+    length: usize,
+) {
+    let hash = position.hash();
 
-    if visited.contains(&hash) {
+    if pv.len() >= length || visited.contains(&hash) {
         return;
     }
 
@@ -273,104 +522,314 @@ fn get_pv(engine: &mut Engine, pv: &mut Vec<Move>, visited: &mut HashSet<u64>) {
     if let Some(entry) = engine.tt().probe(hash) {
         let mut moves = MoveVec::new();
 
-        gen::generate_dyn::<true>(&mut moves, engine.position());
+        gen::generate_dyn::<true>(&mut moves, position);
 
         if moves.moves().contains(&entry.r#move()) {
             pv.push(entry.r#move());
 
-            let undo = engine.position_mut().make(entry.r#move());
+            let undo = position.make(entry.r#move());
 
-            get_pv(engine, pv, visited);
-            engine.position_mut().unmake(undo);
+            get_pv(engine, position, pv, visited, length);
+            position.unmake(undo);
         }
     }
 }
 
-pub fn search(engine: &mut Engine, end: Instant, limits: &SearchLimits) -> Move {
-    if engine.options.ownbook {
-        if let Some(r#move) = engine.book().next(engine.position()) {
-            return r#move;
+// Root move followed by the TT walk from the resulting position
+fn get_line_pv(engine: &Engine, position: &mut Position, r#move: Move, length: usize) -> Vec<Move> {
+    let mut pv = Vec::new();
+
+    if r#move != Move::null() {
+        let mut visited = HashSet::from([position.hash()]);
+        let undo = position.make(r#move);
+
+        pv.push(r#move);
+        get_pv(engine, position, &mut pv, &mut visited, length);
+        position.unmake(undo);
+    }
+
+    pv
+}
+
+pub fn search(engine: &Engine, limits: &SearchLimits) -> SearchResult {
+    let mut root_moves = Vec::new();
+
+    gen::generate_dyn::<true>(&mut root_moves, engine.position());
+
+    if !limits.searchmoves().is_empty() {
+        root_moves.retain(|r#move| limits.searchmoves().contains(r#move));
+    }
+
+    if engine.options.ownbook && !limits.infinite() && limits.searchmoves().is_empty() {
+        if let Some(r#move) = engine.book().next(engine.position(), &mut *engine.rng()) {
+            return SearchResult {
+                pv: vec![r#move],
+                score: None,
+                nodes: 0,
+            };
         }
     }
 
-    let start = Instant::now();
-    let mut best_move = Move::null();
-    let mut min_score = MIN_SCORE;
-    let mut max_score = MAX_SCORE;
+    let threads = usize::max(engine.options.threads, 1);
+    let shared = Shared {
+        nodes: (0..threads).map(|_| AtomicUsize::new(0)).collect(),
+        done: AtomicBool::new(false),
+    };
 
-    let root_ply = engine.position().ply();
+    let mut result = thread::scope(|scope| {
+        for id in 1..threads {
+            let mut position = engine.position().clone();
+            let mut stats = Stats::new(engine, &shared, id, limits, root_moves.clone());
+
+            scope.spawn(move || search_helper(engine, &mut position, &mut stats));
+        }
 
-    let mut stats = Stats {
-        best_index_distribution: Vec::new(),
-        killer_moves: Vec::new(),
-        root_ply,
+        let mut position = engine.position().clone();
+        let mut stats = Stats::new(engine, &shared, 0, limits, root_moves);
+        let result = search_main(engine, &mut position, &mut stats);
+
+        shared.done.store(true, Ordering::Relaxed);
+
+        result
+    });
+
+    result.nodes = shared
+        .nodes
+        .iter()
+        .map(|nodes| nodes.load(Ordering::Relaxed))
+        .sum();
+
+    result
+}
+
+// Helpers only fill the shared TT, odd ones searching a ply deeper to
+// spread the threads over different parts of the tree
+fn search_helper(engine: &Engine, position: &mut Position, stats: &mut Stats<'_>) {
+    for depth in 1..=stats.limits.depth() as u16 {
+        let depth = depth.saturating_add(stats.id as u16 % 2);
+
+        if alpha_beta(engine, position, stats, MIN_SCORE, MAX_SCORE, depth, true).is_none() {
+            break;
+        }
+    }
+}
+
+fn search_main(engine: &Engine, position: &mut Position, stats: &mut Stats<'_>) -> SearchResult {
+    let limits = stats.limits;
+    let mut result = SearchResult {
+        pv: Vec::new(),
+        score: None,
+        nodes: 0,
     };
+    let multipv = usize::clamp(
+        engine.options.multipv,
+        1,
+        usize::max(stats.root_moves.len(), 1),
+    );
+
+    // Aspiration windows per line, around its score from the previous depth
+    let mut windows = vec![(MIN_SCORE, MAX_SCORE); multipv];
 
     for depth in 1..=limits.depth() as u16 {
         stats.best_index_distribution.clear();
+        stats.seldepth = 0;
 
-        let mut score;
+        let root_moves = stats.root_moves.clone();
+        let mut lines = Vec::new();
 
-        loop {
-            let Some(s) = alpha_beta(engine, &mut stats, end, min_score, max_score, depth, true)
-            else {
-                return best_move;
-            };
+        // Search each line with the best moves of the previous lines excluded
+        for (pv_index, window) in windows.iter_mut().enumerate() {
+            let (mut min_score, mut max_score) = *window;
+            let mut score;
 
-            score = s;
+            stats.pv_index = pv_index;
 
-            if score <= min_score || score >= max_score {
-                min_score = MIN_SCORE;
-                max_score = MAX_SCORE;
-            } else {
-                break;
-            }
-        }
+            loop {
+                let Some(s) =
+                    alpha_beta(engine, position, stats, min_score, max_score, depth, true)
+                else {
+                    // A node limit can end the search before the first depth is done
+                    if result.pv.is_empty() {
+                        result.pv.extend(stats.root_moves.first());
+                    }
 
-        let ms = start.elapsed().as_millis();
-        let mut pv = Vec::new();
-        let mut visited = HashSet::new();
+                    return result;
+                };
 
-        get_pv(engine, &mut pv, &mut visited);
+                score = s;
 
-        const WINDOW: i16 = 50;
+                if score <= min_score || score >= max_score {
+                    let bound = if score <= min_score {
+                        "upperbound"
+                    } else {
+                        "lowerbound"
+                    };
 
-        best_move = pv[0];
-        min_score = i16::max(score, MIN_SCORE + WINDOW) - WINDOW;
-        max_score = i16::min(score, MAX_SCORE - WINDOW) + WINDOW;
+                    let pv = get_line_pv(engine, position, stats.root_best, depth as usize);
 
-        print!("info depth {depth} time {ms} score ");
+                    print_info(engine, stats, depth, pv_index, score, bound, &pv);
 
-        if score.abs() > MATE_SCORE {
-            if score > 0 {
-                print!("mate {}", (MAX_SCORE - score - root_ply as i16 + 1) / 2);
-            } else {
-                print!("mate {}", (MIN_SCORE - score + root_ply as i16) / 2);
+                    min_score = MIN_SCORE;
+                    max_score = MAX_SCORE;
+                } else {
+                    break;
+                }
             }
-        } else {
-            print!("cp {score}");
+
+            let pv = get_line_pv(engine, position, stats.root_best, depth as usize);
+
+            const WINDOW: i16 = 50;
+
+            *window = (
+                i16::max(score, MIN_SCORE + WINDOW) - WINDOW,
+                i16::min(score, MAX_SCORE - WINDOW) + WINDOW,
+            );
+
+            stats.root_moves.retain(|r#move| *r#move != stats.root_best);
+            lines.push((score, pv));
         }
 
-        print!(" pv");
+        stats.root_moves = root_moves;
+        lines.sort_by_key(|(score, _)| -score);
 
-        for r#move in pv {
-            print!(" {}", r#move);
+        for (pv_index, (score, pv)) in lines.iter().enumerate() {
+            print_info(engine, stats, depth, pv_index, *score, "", pv);
         }
 
-        println!();
+        let (score, pv) = lines.swap_remove(0);
 
-        let total: usize = stats.best_index_distribution.iter().sum();
+        result = SearchResult {
+            pv,
+            score: Some(score),
+            nodes: 0,
+        };
 
-        eprintln!(
-            "{:.03?}",
-            stats
-                .best_index_distribution
-                .iter()
-                .take(10)
-                .map(|count| *count as f64 / total as f64)
-                .collect::<Vec<_>>()
-        );
+        if engine.is_debug() {
+            let total: usize = stats.best_index_distribution.iter().sum();
+
+            println!(
+                "info string best index distribution {:.03?}",
+                stats
+                    .best_index_distribution
+                    .iter()
+                    .take(10)
+                    .map(|count| *count as f64 / total as f64)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        let mate = mate_distance(score);
+
+        if mate.is_some_and(|mate| mate > 0 && mate as usize <= limits.mate()) {
+            break;
+        }
     }
 
-    best_move
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epd::{Epd, Operand};
+
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+    // Mate distances in moves, negative when the side to move gets mated
+    const MATES: [&str; 7] = [
+        "6k1/5ppp/8/8/8/8/8/R3K3 w - - dm 1;",
+        "6k1/8/6K1/8/8/8/8/R7 w - - dm 1;",
+        "k7/8/1K6/8/8/8/8/7R b - - dm -1;",
+        "r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - dm 2;",
+        "r1b1kb1r/pppp1ppp/5q2/4n3/3KP3/2N3PN/PPP4P/R1BQ1B1R b kq - dm 3;",
+        "8/8/8/8/8/2k5/8/K1q5 w - - dm -1;",
+        "7k/7P/6K1/8/3B4/8/8/8 b - - dm 0;",
+    ];
+
+    #[test]
+    fn mate_suite() {
+        let mut engine = Engine::new();
+
+        engine.setoption("OwnBook", "false");
+
+        for line in MATES {
+            let epd = Epd::parse(line).unwrap();
+            let Some(Operand::Other(operands)) = epd.get("dm") else {
+                panic!("{line} has no dm");
+            };
+            let mate: i16 = operands[0].parse().unwrap();
+            let depth = (2 * mate.abs() + 2).to_string();
+
+            engine.setoption("Clear Hash", "");
+            *engine.position_mut() = epd.position.clone();
+
+            let limits = SearchLimits::parse(&["depth", &depth]).unwrap();
+            let result = search(&engine, &limits);
+
+            assert_eq!(result.score.and_then(mate_distance), Some(mate), "{line}");
+        }
+    }
+
+    #[test]
+    fn node_limit() {
+        let mut engine = Engine::new();
+
+        engine.setoption("OwnBook", "false");
+        *engine.position_mut() = Position::from_str(KIWIPETE);
+
+        for nodes in [1, 100, 5000, 50000] {
+            let limits = SearchLimits::parse(&["nodes", &nodes.to_string()]).unwrap();
+            let result = search(&engine, &limits);
+
+            assert!(
+                result.nodes <= nodes,
+                "{} nodes for a limit of {nodes}",
+                result.nodes
+            );
+            assert!(
+                result.nodes + 16 >= nodes,
+                "{} nodes for a limit of {nodes}",
+                result.nodes
+            );
+            assert!(!result.pv.is_empty(), "no move for a limit of {nodes}");
+        }
+    }
+
+    // Book choices and node counts of fixed depth searches repeat for a fixed
+    // seed, including the default one
+    #[test]
+    fn seeded_runs_repeat() {
+        let run = |seed: Option<&str>| {
+            let mut engine = Engine::new();
+            let mut book_moves = Vec::new();
+            let mut nodes = Vec::new();
+
+            if let Some(seed) = seed {
+                engine.setoption("Seed", seed);
+            }
+
+            let limits = SearchLimits::parse(&["depth", "6"]).unwrap();
+
+            for _ in 0..4 {
+                let result = search(&engine, &limits);
+                let r#move = result.pv[0];
+
+                book_moves.push(r#move);
+                engine.position_mut().make(r#move);
+            }
+
+            engine.setoption("OwnBook", "false");
+
+            for fen in [KIWIPETE, engine.position().fen().as_str()] {
+                *engine.position_mut() = Position::from_str(fen);
+                nodes.push(search(&engine, &limits).nodes);
+            }
+
+            (book_moves, nodes)
+        };
+
+        for seed in [None, Some("12345")] {
+            assert_eq!(run(seed), run(seed));
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use crate::types::Color;
+use crate::{r#move::Move, types::Color};
 use std::time::{Duration, Instant};
 
 #[derive(Debug)]
@@ -6,31 +6,71 @@ pub struct SearchLimits {
     time: [Duration; 2],
     inc: [Duration; 2],
     movetime: Duration,
-    depth: usize,
-    /*
-    searchmoves: Vec<Move>,
-    ponder: bool,
     movestogo: usize,
+    depth: usize,
     nodes: usize,
     mate: usize,
+    searchmoves: Vec<Move>,
+    ponder: bool,
     infinite: bool,
-    */
 }
 
 impl SearchLimits {
-    pub fn parse<'a>(words: &[&str]) -> Self {
-        let mut it = words.iter();
+    const KEYWORDS: [&'static str; 12] = [
+        "searchmoves",
+        "ponder",
+        "wtime",
+        "btime",
+        "winc",
+        "binc",
+        "movestogo",
+        "depth",
+        "nodes",
+        "mate",
+        "movetime",
+        "infinite",
+    ];
+
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        let mut it = words.iter().peekable();
         let mut limits = Self {
             time: [Duration::default(); 2],
             inc: [Duration::default(); 2],
             movetime: Duration::default(),
+            movestogo: 0,
             depth: usize::MAX,
+            nodes: usize::MAX,
+            mate: 0,
+            searchmoves: Vec::new(),
+            ponder: false,
+            infinite: false,
         };
 
         while let Some(command) = it.next() {
-            if let Some(arg) = it.next() {
-                if let Ok(time) = arg.parse::<u64>() {
-                    let duration = Duration::from_millis(time);
+            match *command {
+                "searchmoves" => {
+                    while let Some(word) = it.next_if(|word| !Self::KEYWORDS.contains(word)) {
+                        let r#move =
+                            Move::try_from_str(word).ok_or_else(|| format!("bad move {word}"))?;
+
+                        limits.searchmoves.push(r#move);
+                    }
+                }
+                "ponder" => limits.ponder = true,
+                "infinite" => limits.infinite = true,
+                "wtime" | "btime" | "winc" | "binc" | "movetime" | "movestogo" | "depth"
+                | "nodes" | "mate" => {
+                    let arg = it
+                        .next()
+                        .ok_or_else(|| format!("missing value for {command}"))?;
+
+                    // Some GUIs send negative times once the clock has run out
+                    let value = arg
+                        .parse::<i64>()
+                        .map_err(|_| format!("bad value {arg} for {command}"))?
+                        .max(0) as u64;
+
+                    let duration = Duration::from_millis(value);
 
                     match *command {
                         "wtime" => *Color::White.index_mut(&mut limits.time) = duration,
@@ -38,30 +78,109 @@ impl SearchLimits {
                         "winc" => *Color::White.index_mut(&mut limits.inc) = duration,
                         "binc" => *Color::Black.index_mut(&mut limits.inc) = duration,
                         "movetime" => limits.movetime = duration,
-                        "depth" => limits.depth = time as usize,
-                        _ => (),
+                        "movestogo" => limits.movestogo = value as usize,
+                        "depth" => limits.depth = value as usize,
+                        "nodes" => limits.nodes = value as usize,
+                        "mate" => limits.mate = value as usize,
+                        _ => unreachable!(),
                     }
                 }
+                _ => (),
             }
         }
 
-        limits
+        Ok(limits)
     }
 
     pub fn depth(&self) -> usize {
         self.depth
     }
 
-    pub fn get_end_time(&self, color: Color) -> Instant {
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    pub fn mate(&self) -> usize {
+        self.mate
+    }
+
+    pub fn searchmoves(&self) -> &[Move] {
+        &self.searchmoves
+    }
+
+    pub fn set_searchmoves(&mut self, searchmoves: Vec<Move>) {
+        self.searchmoves = searchmoves;
+    }
+
+    pub fn ponder(&self) -> bool {
+        self.ponder
+    }
+
+    pub fn infinite(&self) -> bool {
+        self.infinite
+    }
+
+    // None if the search should only end on stop or another limit
+    pub fn get_end_time(&self, color: Color, overhead: Duration) -> Option<Instant> {
+        let time = *color.index(&self.time);
+        let limited = self.depth != usize::MAX || self.nodes != usize::MAX || self.mate != 0;
+
+        if self.infinite {
+            return None;
+        }
+
         if self.movetime != Duration::default() {
-            return Instant::now() + self.movetime.mul_f32(0.9);
+            return Some(Instant::now() + self.movetime.mul_f32(0.9).saturating_sub(overhead));
+        }
+
+        if limited && time == Duration::default() {
+            return None;
+        }
+
+        let moves = match self.movestogo {
+            0 => 50,
+            movestogo => usize::min(movestogo + 1, 50),
+        };
+
+        let clock = time.saturating_sub(overhead);
+        let budget = *color.index(&self.inc)
+            + std::cmp::max(Duration::from_millis(10), clock.div_f32(moves as f32));
+
+        // Only a clock that was sent can cap the budget
+        match time {
+            Duration::ZERO => Some(Instant::now() + budget),
+            _ => Some(Instant::now() + std::cmp::min(budget, clock)),
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(words: &[&str]) -> Option<Duration> {
+        let limits = SearchLimits::parse(words).unwrap();
+        let start = Instant::now();
+
+        limits
+            .get_end_time(Color::White, Duration::from_millis(10))
+            .map(|end| end.saturating_duration_since(start))
+    }
+
+    #[test]
+    fn end_time() {
+        let near = |budget: Option<Duration>, millis: u64| {
+            budget.is_some_and(|budget| {
+                budget.abs_diff(Duration::from_millis(millis)).as_millis() < 5
+            })
+        };
 
-        Instant::now()
-            + *color.index(&self.inc)
-            + std::cmp::max(
-                Duration::from_millis(10),
-                color.index(&self.time).div_f32(50.0),
-            )
+        assert!(near(budget(&[]), 10));
+        assert!(near(budget(&["winc", "1000"]), 1010));
+        assert!(near(budget(&["wtime", "5010"]), 100));
+        assert!(near(budget(&["wtime", "20", "winc", "1000"]), 10));
+        assert!(near(budget(&["movetime", "1000"]), 890));
+        assert_eq!(budget(&["depth", "5"]), None);
+        assert_eq!(budget(&["infinite"]), None);
     }
 }
@@ -1,6 +1,19 @@
-use crate::r#move::Move;
+use std::collections::TryReserveError;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-pub const TT_SIZE: usize = 1024 * 1024;
+use crate::{r#move::Move, zobrist::ZobristTable};
+
+// Generations wrap around, ages are compared modulo this
+const GENERATION_COUNT: u32 = 64;
+const CLUSTER_SIZE: usize = 4;
+
+// Hash files start with the magic, the Zobrist seed, the cluster count and
+// the age of the search that wrote them, followed by the packed entries
+const FILE_MAGIC: &[u8; 8] = b"CMHASH02";
 
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub enum Bound {
@@ -19,29 +32,59 @@ pub struct Entry {
     bound: Bound,
 }
 
+// Entries are packed into a data word stored next to the full hash xored with
+// it, so torn writes from other threads are detected as misses
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+// The cluster index comes from the high bits, and one cluster fills one cache line
+#[repr(align(64))]
+struct Cluster {
+    entries: [Slot; CLUSTER_SIZE],
+}
+
+// Shared between search threads without locking
 pub struct TranspositionTable {
-    table: Vec<Entry>,
+    table: Vec<Cluster>,
 }
 
 impl Entry {
-    pub fn null() -> Self {
+    pub fn new(hash: u64, age: u32, r#move: Move, depth: u16, score: i16, bound: Bound) -> Self {
         Self {
-            hash: 0,
-            age: 0,
-            r#move: Move::null(),
-            depth: 0,
-            score: 0,
-            bound: Bound::Exact,
+            hash,
+            age: age % GENERATION_COUNT,
+            r#move,
+            depth: depth.min(u8::MAX as u16),
+            score,
+            bound,
         }
     }
 
-    pub fn new(hash: u64, age: u32, r#move: Move, depth: u16, score: i16, bound: Bound) -> Self {
+    // The top bit tells written entries from empty slots
+    fn pack(&self) -> u64 {
+        self.r#move.bits() as u64
+            | (self.score as u16 as u64) << 16
+            | (self.depth as u64) << 32
+            | (self.bound as u64) << 40
+            | (self.age as u64) << 42
+            | 1 << 63
+    }
+
+    fn unpack(hash: u64, data: u64) -> Self {
+        let bound = match data >> 40 & 3 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+
         Self {
             hash,
-            age,
-            r#move,
-            depth,
-            score,
+            age: (data >> 42 & 0x3F) as u32,
+            r#move: Move::from_bits(data as u16),
+            depth: (data >> 32 & 0xFF) as u16,
+            score: (data >> 16) as u16 as i16,
             bound,
         }
     }
@@ -66,38 +109,296 @@ impl Entry {
         self.bound
     }
 
-    pub fn value(&self) -> u32 {
-        let bound_score = match self.bound {
-            Bound::Exact => 1,
-            _ => 0,
-        };
+    // Searches since this entry was written, across generation wraparound
+    fn relative_age(&self, age: u32) -> u32 {
+        (age + GENERATION_COUNT - self.age) % GENERATION_COUNT
+    }
 
-        self.age * 16 + self.depth as u32 * 2 + bound_score
+    // Lowest is replaced first, old and shallow entries go before recent deep ones
+    fn replace_value(&self, age: u32) -> i32 {
+        self.depth as i32 - 8 * self.relative_age(age) as i32
+    }
+}
+
+impl Slot {
+    // The hash of the entry and its data, 0 if empty
+    fn load(&self) -> (u64, u64) {
+        let data = self.data.load(Ordering::Relaxed);
+
+        (self.key.load(Ordering::Relaxed) ^ data, data)
+    }
+
+    fn store(&self, hash: u64, data: u64) {
+        self.key.store(hash ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
     }
 }
 
 impl TranspositionTable {
-    pub fn new() -> Self {
-        Self {
-            table: vec![Entry::null(); TT_SIZE],
+    pub const MAX_MEGABYTES: usize = 65536;
+
+    // Fails instead of aborting when the memory is not available
+    pub fn new(megabytes: usize) -> Result<Self, TryReserveError> {
+        Self::with_clusters(megabytes * 1024 * 1024 / mem::size_of::<Cluster>())
+    }
+
+    fn with_clusters(size: usize) -> Result<Self, TryReserveError> {
+        let size = usize::max(size, 1);
+        let mut table = Vec::new();
+
+        table.try_reserve_exact(size)?;
+        table.extend((0..size).map(|_| Cluster {
+            entries: [const {
+                Slot {
+                    key: AtomicU64::new(0),
+                    data: AtomicU64::new(0),
+                }
+            }; CLUSTER_SIZE],
+        }));
+
+        Ok(Self { table })
+    }
+
+    pub fn megabytes(&self) -> usize {
+        self.table.len() * mem::size_of::<Cluster>() / (1024 * 1024)
+    }
+
+    pub fn clear(&mut self) {
+        for cluster in &mut self.table {
+            for slot in &mut cluster.entries {
+                *slot.key.get_mut() = 0;
+                *slot.data.get_mut() = 0;
+            }
         }
     }
 
-    pub fn insert(&mut self, entry: Entry) {
-        let table_entry = &mut self.table[entry.hash as usize % TT_SIZE];
+    fn cluster(&self, hash: u64) -> &Cluster {
+        let index = ((hash as u128 * self.table.len() as u128) >> 64) as usize;
+
+        &self.table[index]
+    }
 
-        if entry.value() > table_entry.value() {
-            *table_entry = entry;
+    // Hint to load the cluster of a position that is about to be probed
+    pub fn prefetch(&self, hash: u64) {
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+            _mm_prefetch::<_MM_HINT_T0>(self.cluster(hash) as *const Cluster as *const i8);
         }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = hash;
+    }
+
+    pub fn insert(&self, entry: Entry) {
+        let cluster = self.cluster(entry.hash);
+        let mut replace = &cluster.entries[0];
+        let mut replace_value = i32::MAX;
+
+        for slot in &cluster.entries {
+            let (hash, data) = slot.load();
+
+            // Empty slots are used first
+            if data == 0 {
+                if replace_value > i32::MIN {
+                    replace = slot;
+                    replace_value = i32::MIN;
+                }
+
+                continue;
+            }
+
+            let old = Entry::unpack(entry.hash, data);
+
+            // Same position, only a much shallower result from this search is kept out
+            if hash == entry.hash {
+                if entry.bound == Bound::Exact
+                    || entry.depth + 2 >= old.depth
+                    || old.relative_age(entry.age) != 0
+                {
+                    let mut entry = entry;
+
+                    if entry.r#move == Move::null() {
+                        entry.r#move = old.r#move;
+                    }
+
+                    slot.store(entry.hash, entry.pack());
+                }
+
+                return;
+            }
+
+            if old.replace_value(entry.age) < replace_value {
+                replace = slot;
+                replace_value = old.replace_value(entry.age);
+            }
+        }
+
+        replace.store(entry.hash, entry.pack());
+    }
+
+    // Permille of sampled entries written during the current search
+    pub fn hashfull(&self, age: u32) -> usize {
+        let age = age % GENERATION_COUNT;
+        let clusters = usize::min(1000 / CLUSTER_SIZE, self.table.len());
+
+        let count = self.table[..clusters]
+            .iter()
+            .flat_map(|cluster| &cluster.entries)
+            .map(|slot| slot.data.load(Ordering::Relaxed))
+            .filter(|&data| data != 0 && Entry::unpack(0, data).age == age)
+            .count();
+
+        count * 1000 / (clusters * CLUSTER_SIZE)
     }
 
     pub fn probe(&self, hash: u64) -> Option<Entry> {
-        let entry = self.table[hash as usize % TT_SIZE];
+        self.cluster(hash)
+            .entries
+            .iter()
+            .map(Slot::load)
+            .find(|&(key, data)| data != 0 && key == hash)
+            .map(|(_, data)| Entry::unpack(hash, data))
+    }
+
+    pub fn save(&self, path: &Path, age: u32) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(FILE_MAGIC)?;
+        writer.write_all(&ZobristTable::SEED.to_le_bytes())?;
+        writer.write_all(&(self.table.len() as u64).to_le_bytes())?;
+        writer.write_all(&(age as u64).to_le_bytes())?;
+
+        for slot in self.table.iter().flat_map(|cluster| &cluster.entries) {
+            writer.write_all(&slot.key.load(Ordering::Relaxed).to_le_bytes())?;
+            writer.write_all(&slot.data.load(Ordering::Relaxed).to_le_bytes())?;
+        }
+
+        writer.flush()
+    }
 
-        if entry.hash == hash {
-            Some(entry)
-        } else {
-            None
+    // Returns the table sized as it was saved, and the age to continue from
+    pub fn load(path: &Path) -> io::Result<(Self, u32)> {
+        fn invalid(message: String) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, message)
         }
+
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut word = [0; 8];
+        let mut read_word = |reader: &mut BufReader<File>| {
+            reader
+                .read_exact(&mut word)
+                .map(|_| u64::from_le_bytes(word))
+        };
+
+        let mut magic = [0; FILE_MAGIC.len()];
+
+        reader.read_exact(&mut magic)?;
+
+        if &magic != FILE_MAGIC {
+            return Err(invalid("not a hash file".to_string()));
+        }
+
+        let seed = read_word(&mut reader)?;
+
+        if seed != ZobristTable::SEED {
+            return Err(invalid(format!("hash file has zobrist seed {seed:#x}")));
+        }
+
+        let size = read_word(&mut reader)?;
+        let age = read_word(&mut reader)? as u32;
+        let length = reader.get_ref().metadata()?.len();
+        let cluster = mem::size_of::<Cluster>() as u64;
+
+        // The header is checked against the file before it decides the allocation
+        if size == 0
+            || size > (Self::MAX_MEGABYTES * 1024 * 1024) as u64 / cluster
+            || length != (FILE_MAGIC.len() + 3 * 8) as u64 + size * cluster
+        {
+            return Err(invalid(format!("hash file does not hold {size} clusters")));
+        }
+
+        let mut tt = Self::with_clusters(size as usize)
+            .map_err(|err| io::Error::new(io::ErrorKind::OutOfMemory, err))?;
+
+        for slot in tt.table.iter_mut().flat_map(|cluster| &mut cluster.entries) {
+            *slot.key.get_mut() = read_word(&mut reader)?;
+            *slot.data.get_mut() = read_word(&mut reader)?;
+        }
+
+        Ok((tt, age % GENERATION_COUNT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf};
+
+    // Removed on drop, so a failed assert does not leave it behind
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn full_key() {
+        let tt = TranspositionTable::new(1).unwrap();
+        let hash = 0x0123_4567_89AB_CDEF;
+
+        tt.insert(Entry::new(hash, 0, Move::null(), 3, 42, Bound::Exact));
+
+        assert_eq!(tt.probe(hash).map(|entry| entry.score()), Some(42));
+
+        // Same cluster and same low bits, but another position
+        assert_eq!(tt.probe(hash ^ 1 << 16), None);
+    }
+
+    #[test]
+    fn save_load() {
+        let file =
+            TempFile(env::temp_dir().join(format!("chess-master-{}.hash", std::process::id())));
+        let path = &file.0;
+        let tt = TranspositionTable::new(1).unwrap();
+        let r#move = Move::from_str("e2e4");
+
+        for hash in (0..1000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)) {
+            tt.insert(Entry::new(hash, 5, r#move, 3, hash as i16, Bound::Lower));
+        }
+
+        tt.save(path, 5).unwrap();
+
+        let (loaded, age) = TranspositionTable::load(path).unwrap();
+
+        assert_eq!(age, 5);
+        assert_eq!(loaded.megabytes(), 1);
+
+        for hash in (0..1000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)) {
+            assert_eq!(loaded.probe(hash), tt.probe(hash));
+        }
+
+        // A table written with other Zobrist keys is refused
+        let mut data = fs::read(path).unwrap();
+
+        data[FILE_MAGIC.len()] ^= 1;
+        fs::write(path, &data).unwrap();
+
+        let err = TranspositionTable::load(path).err().unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // So is a header claiming more clusters than the file holds
+        data[FILE_MAGIC.len()] ^= 1;
+        data[FILE_MAGIC.len() + 8..FILE_MAGIC.len() + 16]
+            .copy_from_slice(&(1u64 << 60).to_le_bytes());
+        fs::write(path, &data).unwrap();
+
+        let err = TranspositionTable::load(path).err().unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
     }
 }
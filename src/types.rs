@@ -1,6 +1,9 @@
 use std::fmt;
 use std::mem;
-use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
+use std::ops::{
+    Add, AddAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Mul, Neg, Not,
+    Sub, SubAssign,
+};
 
 use crate::shift::Offset;
 use crate::shift::Shift;
@@ -54,6 +57,18 @@ impl CastlingRights {
         castling_rights
     }
 
+    pub fn try_from_str(s: &str) -> Option<Self> {
+        if s == "-" {
+            return Some(Self::NONE);
+        }
+
+        if s.is_empty() || !s.chars().all(|ch| "KQkq".contains(ch)) {
+            return None;
+        }
+
+        Some(Self::from_str(s))
+    }
+
     pub fn index<T>(self, array: &[T; 16]) -> &T {
         unsafe { &array.get_unchecked(self.0 as usize) }
     }
@@ -89,6 +104,75 @@ impl Not for CastlingRights {
     }
 }
 
+// Midgame and endgame values of an evaluation term
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct Score {
+    pub mg: i16,
+    pub eg: i16,
+}
+
+impl Score {
+    pub const ZERO: Score = Score::new(0, 0);
+
+    // Phase of the midgame, from 0 for a pure endgame to this
+    pub const PHASE_MAX: i16 = 256;
+
+    pub const fn new(mg: i16, eg: i16) -> Self {
+        Self { mg, eg }
+    }
+
+    pub fn taper(self, phase: i16) -> i16 {
+        let phase = phase as i32;
+        let max = Self::PHASE_MAX as i32;
+
+        ((self.mg as i32 * phase + self.eg as i32 * (max - phase)) / max) as i16
+    }
+}
+
+impl Add for Score {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Score {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl SubAssign for Score {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for Score {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        Self::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i16> for Score {
+    type Output = Self;
+
+    fn mul(self, rhs: i16) -> Self::Output {
+        Self::new(self.mg * rhs, self.eg * rhs)
+    }
+}
+
 macro_rules! count {
     () => { 0 };
     ($first:tt $($rest:tt)*) => { 1 + count!($($rest)*) };
@@ -175,12 +259,6 @@ define_enum! {
         A7, B7, C7, D7, E7, F7, G7, H7,
         A8, B8, C8, D8, E8, F8, G8, H8,
     }
-
-    pub enum Phase {
-        Opening,
-        Middle,
-        Endgame,
-    }
 }
 
 pub trait ConstColor {
@@ -259,11 +337,22 @@ impl ConstColor for ConstBlack {
 }
 
 impl Color {
-    pub fn from_char(ch: char) -> Self {
+    pub fn try_from_char(ch: char) -> Option<Self> {
         match ch {
-            'w' => Self::White,
-            'b' => Self::Black,
-            _ => panic!("bad color {ch}"),
+            'w' => Some(Self::White),
+            'b' => Some(Self::Black),
+            _ => None,
+        }
+    }
+
+    pub fn from_char(ch: char) -> Self {
+        Self::try_from_char(ch).unwrap_or_else(|| panic!("bad color {ch}"))
+    }
+
+    pub fn try_from_str(s: &str) -> Option<Self> {
+        match s.as_bytes() {
+            [ch] => Self::try_from_char(*ch as char),
+            _ => None,
         }
     }
 
@@ -280,18 +369,22 @@ impl Color {
 }
 
 impl Kind {
-    pub fn from_char(ch: char) -> Self {
+    pub fn try_from_char(ch: char) -> Option<Self> {
         match ch {
-            'p' => Self::Pawn,
-            'n' => Self::Knight,
-            'b' => Self::Bishop,
-            'r' => Self::Rook,
-            'q' => Self::Queen,
-            'k' => Self::King,
-            _ => panic!("bad piece kind {ch}"),
+            'p' => Some(Self::Pawn),
+            'n' => Some(Self::Knight),
+            'b' => Some(Self::Bishop),
+            'r' => Some(Self::Rook),
+            'q' => Some(Self::Queen),
+            'k' => Some(Self::King),
+            _ => None,
         }
     }
 
+    pub fn from_char(ch: char) -> Self {
+        Self::try_from_char(ch).unwrap_or_else(|| panic!("bad piece kind {ch}"))
+    }
+
     pub fn from_str(s: &str) -> Self {
         Self::from_char(s.chars().nth(0).unwrap())
     }
@@ -331,15 +424,19 @@ impl Piece {
         unsafe { mem::transmute(self as u8 >> 1) }
     }
 
-    pub fn from_char(ch: char) -> Self {
-        Self::new(
+    pub fn try_from_char(ch: char) -> Option<Self> {
+        Some(Self::new(
             if ch.is_ascii_uppercase() {
                 Color::White
             } else {
                 Color::Black
             },
-            Kind::from_char(ch.to_ascii_lowercase()),
-        )
+            Kind::try_from_char(ch.to_ascii_lowercase())?,
+        ))
+    }
+
+    pub fn from_char(ch: char) -> Self {
+        Self::try_from_char(ch).unwrap_or_else(|| panic!("bad piece {ch}"))
     }
 
     pub fn from_str(s: &str) -> Self {
@@ -357,20 +454,24 @@ impl Piece {
 }
 
 impl File {
-    pub fn from_char(ch: char) -> Self {
+    pub fn try_from_char(ch: char) -> Option<Self> {
         match ch {
-            'a' => Self::A,
-            'b' => Self::B,
-            'c' => Self::C,
-            'd' => Self::D,
-            'e' => Self::E,
-            'f' => Self::F,
-            'g' => Self::G,
-            'h' => Self::H,
-            _ => panic!("bad file {ch}"),
+            'a' => Some(Self::A),
+            'b' => Some(Self::B),
+            'c' => Some(Self::C),
+            'd' => Some(Self::D),
+            'e' => Some(Self::E),
+            'f' => Some(Self::F),
+            'g' => Some(Self::G),
+            'h' => Some(Self::H),
+            _ => None,
         }
     }
 
+    pub fn from_char(ch: char) -> Self {
+        Self::try_from_char(ch).unwrap_or_else(|| panic!("bad file {ch}"))
+    }
+
     pub fn from_str(s: &str) -> Self {
         Self::from_char(s.chars().nth(0).unwrap())
     }
@@ -390,20 +491,24 @@ impl File {
 }
 
 impl Rank {
-    pub fn from_char(ch: char) -> Self {
+    pub fn try_from_char(ch: char) -> Option<Self> {
         match ch {
-            '1' => Self::_1,
-            '2' => Self::_2,
-            '3' => Self::_3,
-            '4' => Self::_4,
-            '5' => Self::_5,
-            '6' => Self::_6,
-            '7' => Self::_7,
-            '8' => Self::_8,
-            _ => panic!("bad file {ch}"),
+            '1' => Some(Self::_1),
+            '2' => Some(Self::_2),
+            '3' => Some(Self::_3),
+            '4' => Some(Self::_4),
+            '5' => Some(Self::_5),
+            '6' => Some(Self::_6),
+            '7' => Some(Self::_7),
+            '8' => Some(Self::_8),
+            _ => None,
         }
     }
 
+    pub fn from_char(ch: char) -> Self {
+        Self::try_from_char(ch).unwrap_or_else(|| panic!("bad rank {ch}"))
+    }
+
     pub fn from_str(s: &str) -> Self {
         Self::from_char(s.chars().nth(0).unwrap())
     }
@@ -449,6 +554,16 @@ impl Square {
         Self::new(file, rank)
     }
 
+    pub fn try_from_str(s: &str) -> Option<Self> {
+        match s.as_bytes() {
+            [file, rank] => Some(Self::new(
+                File::try_from_char(*file as char)?,
+                Rank::try_from_char(*rank as char)?,
+            )),
+            _ => None,
+        }
+    }
+
     pub const fn r#for(self, color: Color) -> Self {
         match color {
             Color::White => self,
@@ -0,0 +1,187 @@
+use crate::{
+    gen::{self, MoveVec},
+    options::Options,
+    position::Position,
+    r#move::Move,
+    searchlimits::SearchLimits,
+};
+
+pub enum Command {
+    Uci,
+    Debug(bool),
+    IsReady,
+    SetOption {
+        name: String,
+        value: String,
+    },
+    UciNewGame,
+    Position(Position),
+    Go(SearchLimits),
+    Perft(usize),
+    PerftSuite {
+        path: String,
+        depth: usize,
+    },
+    Bench {
+        depth: usize,
+        hash: usize,
+        threads: usize,
+    },
+    Eval,
+    Stop,
+    PonderHit,
+    Quit,
+}
+
+impl Command {
+    const COMMANDS: [&'static str; 15] = [
+        "uci",
+        "debug",
+        "isready",
+        "setoption",
+        "ucinewgame",
+        "position",
+        "go",
+        "perft",
+        "perftsuite",
+        "bench",
+        "eval",
+        "stop",
+        "ponderhit",
+        "quit",
+        "register",
+    ];
+
+    // None for empty lines and commands we have nothing to do for
+    pub fn parse(line: &str) -> Result<Option<Self>, String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        // Unknown tokens before a command are skipped
+        let Some(start) = words.iter().position(|word| Self::COMMANDS.contains(word)) else {
+            return Err(format!("unknown command {}", words[0]));
+        };
+
+        let args = &words[start + 1..];
+
+        let command = match words[start] {
+            "uci" => Self::Uci,
+            "debug" => match args.first() {
+                Some(&"on") => Self::Debug(true),
+                Some(&"off") => Self::Debug(false),
+                _ => return Err("expected debug on or debug off".to_string()),
+            },
+            "isready" => Self::IsReady,
+            "setoption" => Self::parse_setoption(args)?,
+            "ucinewgame" => Self::UciNewGame,
+            "position" => Self::Position(Self::parse_position(args)?),
+            "go" if args.first() == Some(&"perft") => Self::Perft(Self::parse_depth(&args[1..])?),
+            "go" => Self::Go(SearchLimits::parse(args)?),
+            "perft" => Self::Perft(Self::parse_depth(args)?),
+            "perftsuite" => Self::parse_perftsuite(args)?,
+            "bench" => Self::parse_bench(args)?,
+            "eval" => Self::Eval,
+            "stop" => Self::Stop,
+            "ponderhit" => Self::PonderHit,
+            "quit" => Self::Quit,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(command))
+    }
+
+    fn parse_setoption(args: &[&str]) -> Result<Self, String> {
+        let mut it = args.splitn(2, |&arg| arg == "value");
+
+        let name = match it.next() {
+            Some(["name", name @ ..]) if !name.is_empty() => name.join(" "),
+            _ => return Err("expected setoption name <id> [value <x>]".to_string()),
+        };
+
+        let value = it.next().map(|value| value.join(" ")).unwrap_or_default();
+
+        Ok(Self::SetOption { name, value })
+    }
+
+    fn parse_depth(args: &[&str]) -> Result<usize, String> {
+        match args.first() {
+            Some(arg) => arg.parse().map_err(|_| format!("bad depth {arg}")),
+            None => Ok(1),
+        }
+    }
+
+    fn parse_perftsuite(args: &[&str]) -> Result<Self, String> {
+        let depth = match args.get(1) {
+            Some(arg) => arg.parse().map_err(|_| format!("bad depth {arg}"))?,
+            None => usize::MAX,
+        };
+
+        match args.first() {
+            Some(path) => Ok(Self::PerftSuite {
+                path: path.to_string(),
+                depth,
+            }),
+            None => Err("expected perftsuite <file.epd> [maxdepth]".to_string()),
+        }
+    }
+
+    fn parse_bench(args: &[&str]) -> Result<Self, String> {
+        let mut values = [9, 16, 1];
+
+        for (value, arg) in values.iter_mut().zip(args) {
+            *value = match arg.parse() {
+                Ok(arg) if arg > 0 => arg,
+                _ => return Err("expected bench [depth] [hash] [threads]".to_string()),
+            };
+        }
+
+        // Held to the ranges of the options they stand in for
+        for (name, arg) in ["Hash", "Threads"].into_iter().zip(args.iter().skip(1)) {
+            Options::declared(name)
+                .unwrap()
+                .kind
+                .parse(arg)
+                .map_err(|err| format!("bad bench {}: {err}", name.to_lowercase()))?;
+        }
+
+        let [depth, hash, threads] = values;
+
+        Ok(Self::Bench {
+            depth,
+            hash,
+            threads,
+        })
+    }
+
+    fn parse_position(args: &[&str]) -> Result<Position, String> {
+        let (fen, moves) = match args.iter().position(|&arg| arg == "moves") {
+            Some(index) => (&args[..index], &args[index + 1..]),
+            None => (args, &[][..]),
+        };
+
+        let mut position = match fen {
+            ["startpos"] => Position::new(),
+            ["fen", fen @ ..] => Position::from_fields(fen)
+                .map_err(|err| format!("invalid fen {}: {err}", fen.join(" ")))?,
+            _ => return Err("expected position startpos or position fen <fen>".to_string()),
+        };
+
+        for word in moves {
+            let mut legal = MoveVec::new();
+
+            gen::generate_dyn::<true>(&mut legal, &position);
+
+            match Move::try_from_str(word) {
+                Some(r#move) if legal.moves().contains(&r#move) => {
+                    position.make(r#move);
+                }
+                _ => return Err(format!("illegal move {word} in {}", position.fen())),
+            }
+        }
+
+        Ok(position)
+    }
+}
@@ -1,5 +1,3 @@
-use rand::prelude::*;
-
 use crate::types::{CastlingRights, File, Piece, Square};
 
 pub struct ZobristTable {
@@ -9,15 +7,38 @@ pub struct ZobristTable {
     en_passant: [u64; 8],
 }
 
+// SplitMix64, so the keys only depend on the seed and hashes are stable
+// across runs, builds and machines
+struct KeyGenerator(u64);
+
+impl KeyGenerator {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.0;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill<const N: usize>(&mut self) -> [u64; N] {
+        [(); N].map(|_| self.next())
+    }
+}
+
 impl ZobristTable {
+    // Recorded in saved hash files, which are useless with different keys
+    pub const SEED: u64 = 0x4348_4553_534D_4153;
+
     pub fn new() -> Self {
-        let mut rng = rand::rng();
+        let mut keys = KeyGenerator(Self::SEED);
 
         Self {
-            piece: rng.random(),
-            color: rng.random(),
-            castling_rights: rng.random(),
-            en_passant: rng.random(),
+            piece: [(); 12].map(|_| keys.fill()),
+            color: keys.next(),
+            castling_rights: keys.fill(),
+            en_passant: keys.fill(),
         }
     }
 
@@ -37,3 +58,9 @@ impl ZobristTable {
         *file.index(&self.en_passant)
     }
 }
+
+impl Default for ZobristTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}